@@ -0,0 +1,194 @@
+// runs several vms on their own threads, passing values between them through named mailboxes
+// only available with the `sync` feature, since values have to be able to cross threads
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use crate::lsm::error::VMError;
+use crate::lsm::instruction::{Instruction, OperandKind};
+use crate::lsm::vm::{RunState, Value, VM};
+
+/*
+instruction set for reference
+NAME - OPCODE - DETAILS
+SEND - 24 - pops a mailbox name then a value, and posts the value to that mailbox
+RECV - 25 - pops a mailbox name, and pushes the next value posted to it, waiting if there isn't one yet
+ */
+
+pub const ACTOR_INSTRUCTION_SET: &[Instruction] = &[
+    Instruction {
+        name: "SEND",
+        opcode: 24,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            let b = vm.pop()?;
+            let actor = vm.actor().ok_or(VMError::NoActorRuntime)?;
+
+            actor.send(&a.to_str()?, b);
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "RECV",
+        opcode: 25,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            let actor = vm.actor().ok_or(VMError::NoActorRuntime)?;
+
+            let value = actor.receive(&a.to_str()?)?;
+            vm.push(value)?;
+            Ok(())
+        }
+    },
+];
+
+#[derive(Default)]
+struct PostOffice {
+    state: Mutex<PostOfficeState>,
+    delivered: Condvar, // signalled whenever something is sent or a vm stops running
+}
+
+#[derive(Default)]
+struct PostOfficeState {
+    mailboxes: HashMap<String, VecDeque<Value>>,
+    waiting: HashMap<String, usize>, // how many vms are blocked in RECV on each mailbox
+    running: usize, // vms that have neither halted nor blocked in RECV
+    closed: bool, // nothing can be sent any more, so empty mailboxes never fill up again
+}
+
+impl PostOfficeState {
+    // nobody is running and nobody blocked has anything to receive, so nothing will ever be sent again
+    fn deadlocked(&self) -> bool {
+        self.running == 0 && self.waiting.keys().all(|name| self.is_empty(name))
+    }
+
+    fn is_empty(&self, mailbox: &str) -> bool {
+        self.mailboxes.get(mailbox).is_none_or(|queue| queue.is_empty())
+    }
+
+    fn take(&mut self, mailbox: &str) -> Option<Value> {
+        self.mailboxes.get_mut(mailbox).and_then(|queue| queue.pop_front())
+    }
+}
+
+// a vm's way into the runtime's mailboxes
+#[derive(Clone, Default)]
+pub struct ActorHandle {
+    office: Arc<PostOffice>,
+}
+
+impl ActorHandle {
+    fn lock(&self) -> MutexGuard<'_, PostOfficeState> {
+        self.office.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // posts the value to the mailbox, never blocks
+    pub fn send(&self, mailbox: &str, value: Value) {
+        let mut state = self.lock();
+        state.mailboxes.entry(mailbox.to_string()).or_default().push_back(value);
+        self.office.delivered.notify_all();
+    }
+
+    // takes the next value from the mailbox, blocking until one is sent
+    // once every vm is halted or blocked with nothing to receive, the mailbox is closed instead
+    pub fn receive(&self, mailbox: &str) -> Result<Value, VMError> {
+        let mut state = self.lock();
+
+        if let Some(value) = state.take(mailbox) {
+            return Ok(value);
+        }
+
+        state.running -= 1;
+        *state.waiting.entry(mailbox.to_string()).or_default() += 1;
+
+        let result = loop {
+            if let Some(value) = state.take(mailbox) {
+                break Ok(value);
+            }
+
+            if !state.closed && state.deadlocked() {
+                state.closed = true;
+                self.office.delivered.notify_all();
+            }
+
+            if state.closed {
+                break Err(VMError::MailboxClosed(mailbox.to_string()));
+            }
+
+            state = self.office.delivered.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+        };
+
+        if let Some(waiting) = state.waiting.get_mut(mailbox) {
+            *waiting -= 1;
+            if *waiting == 0 {
+                state.waiting.remove(mailbox);
+            }
+        }
+        state.running += 1;
+
+        result
+    }
+
+    // a vm has halted, which might leave everyone else blocked forever
+    fn finished(&self) {
+        let mut state = self.lock();
+        state.running -= 1;
+        self.office.delivered.notify_all();
+    }
+}
+
+// makes sure a vm counts as finished even if its thread panics, otherwise the rest could wait forever
+struct FinishGuard(ActorHandle);
+
+impl Drop for FinishGuard {
+    fn drop(&mut self) {
+        self.0.finished();
+    }
+}
+
+#[derive(Default)]
+pub struct ActorRuntime {
+    handle: ActorHandle,
+    vms: Vec<VM>,
+}
+
+impl ActorRuntime {
+    pub fn new() -> ActorRuntime {
+        ActorRuntime::default()
+    }
+
+    // adds a vm to be run, it needs ACTOR_INSTRUCTION_SET in its instruction set to use SEND/RECV
+    pub fn spawn(&mut self, mut vm: VM) {
+        vm.set_actor(Some(self.handle.clone()));
+        self.vms.push(vm);
+    }
+
+    // runs every vm on its own thread until they have all halted, giving back how each one ended in spawn order
+    pub fn run(self) -> Vec<RunState> {
+        self.handle.lock().running = self.vms.len();
+
+        let threads: Vec<_> = self.vms.into_iter()
+            .map(|mut vm| {
+                let guard = FinishGuard(self.handle.clone());
+
+                thread::spawn(move || {
+                    let _guard = guard;
+                    let mut state = vm.run();
+
+                    loop {
+                        state = match state {
+                            RunState::Halted | RunState::Error(_) => return state,
+                            // there's no host on the other end of an actor, so YIELD and IN just get nil
+                            _ => vm.resume(None),
+                        };
+                    }
+                })
+            })
+            .collect();
+
+        threads.into_iter()
+            .map(|thread| thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect()
+    }
+}
@@ -0,0 +1,305 @@
+// ADD, SUB, MUL, DIV and MOD for every numeric type
+// two ints give an int, an int with a number gives a number, the same as most languages do it
+// what happens when an int result doesn't fit is up to the vm's OverflowMode, or the W/C/S instructions ask for one
+// bigints can be mixed with ints, and any result that fits is turned back into an int
+// a rational with an int, bigint or another rational is exact and stays a rational, with a number it's a number
+use std::cmp::Ordering;
+use crate::lsm::bigint::BigInt;
+use crate::lsm::error::VMError;
+use crate::lsm::rational::Rational;
+use crate::lsm::shared::Shared;
+use crate::lsm::vm::{OperandSize, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArithmeticOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+// what to do with an int result too big (or small) for an i64
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowMode {
+    Wrapping, // two's complement, like the hardware does it
+    #[default]
+    Checked, // raise an IntegerOverflow error, which a TRY can catch
+    Saturating, // stick at i64::MIN or i64::MAX
+    Promote, // carry on as a bigint
+}
+
+impl OverflowMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverflowMode::Wrapping => "wrapping",
+            OverflowMode::Checked => "checked",
+            OverflowMode::Saturating => "saturating",
+            OverflowMode::Promote => "promote",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<OverflowMode> {
+        match name {
+            "wrapping" => Some(OverflowMode::Wrapping),
+            "checked" => Some(OverflowMode::Checked),
+            "saturating" => Some(OverflowMode::Saturating),
+            "promote" => Some(OverflowMode::Promote),
+            _ => None,
+        }
+    }
+}
+
+impl ArithmeticOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArithmeticOp::Add => "ADD",
+            ArithmeticOp::Sub => "SUB",
+            ArithmeticOp::Mul => "MUL",
+            ArithmeticOp::Div => "DIV",
+            ArithmeticOp::Mod => "MOD",
+        }
+    }
+}
+
+// left is the second value on the stack and right the top, so SUB is left - right
+pub fn arithmetic(op: ArithmeticOp, left: Value, right: Value, mode: OverflowMode) -> Result<Value, VMError> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => match int_arithmetic(op, a, b, mode) {
+            // int_arithmetic only deals in i64s, so promoting happens out here
+            Err(VMError::IntegerOverflow(_)) if mode == OverflowMode::Promote => big_arithmetic(op, &BigInt::from(a), &BigInt::from(b)),
+            result => result.map(Value::Int),
+        },
+        (Value::BigInt(a), Value::BigInt(b)) => big_arithmetic(op, &a, &b),
+        (Value::BigInt(a), Value::Int(b)) => big_arithmetic(op, &a, &BigInt::from(b)),
+        (Value::Int(a), Value::BigInt(b)) => big_arithmetic(op, &BigInt::from(a), &b),
+        (left @ Value::Rational(_), right) | (left, right @ Value::Rational(_)) => match (to_rational(&left), to_rational(&right)) {
+            (Some(a), Some(b)) => rational_arithmetic(op, &a, &b),
+            _ => float_arithmetic(op, &left, &right),
+        },
+        (left, right) => float_arithmetic(op, &left, &right),
+    }
+}
+
+// anything with a number in it, or something that isn't numeric at all
+fn float_arithmetic(op: ArithmeticOp, left: &Value, right: &Value) -> Result<Value, VMError> {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(a), Some(b)) => Ok(Value::Number(number_arithmetic(op, a, b))),
+        (Some(_), None) => Err(VMError::TypeMismatch { expected: "number", found: right.type_name() }),
+        (None, _) => Err(VMError::TypeMismatch { expected: "number", found: left.type_name() }),
+    }
+}
+
+// an exact value as a rational, None for numbers and anything that isn't numeric
+pub fn to_rational(value: &Value) -> Option<Rational> {
+    match value {
+        Value::Int(n) => Some(Rational::from(*n)),
+        Value::BigInt(n) => Some(Rational::from(n.as_ref().clone())),
+        Value::Rational(n) => Some(n.as_ref().clone()),
+        _ => None,
+    }
+}
+
+// nothing can overflow here either, and a whole result is still a rational
+pub fn rational_arithmetic(op: ArithmeticOp, a: &Rational, b: &Rational) -> Result<Value, VMError> {
+    let result = match op {
+        ArithmeticOp::Add => a + b,
+        ArithmeticOp::Sub => a - b,
+        ArithmeticOp::Mul => a * b,
+        ArithmeticOp::Div => a.checked_div(b).ok_or(VMError::DivisionByZero)?,
+        ArithmeticOp::Mod => a.checked_rem(b).ok_or(VMError::DivisionByZero)?,
+    };
+
+    Ok(Value::Rational(Shared::new(result)))
+}
+
+// the same rules as ints, except nothing can overflow
+pub fn big_arithmetic(op: ArithmeticOp, a: &BigInt, b: &BigInt) -> Result<Value, VMError> {
+    let result = match op {
+        ArithmeticOp::Add => a + b,
+        ArithmeticOp::Sub => a - b,
+        ArithmeticOp::Mul => a * b,
+        ArithmeticOp::Div => a.divmod(b).ok_or(VMError::DivisionByZero)?.0,
+        ArithmeticOp::Mod => a.divmod(b).ok_or(VMError::DivisionByZero)?.1,
+    };
+
+    Ok(Value::from(result))
+}
+
+// how two numeric values compare, ints, bigints and rationals exactly, an int with a number exactly too,
+// and anything else with a number in it as f64s
+// None if either isn't numeric, or one's nan
+pub fn numeric_cmp(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Int(a), Value::Number(b)) => int_number_cmp(*a, *b),
+        (Value::Number(a), Value::Int(b)) => int_number_cmp(*b, *a).map(Ordering::reverse),
+        (Value::BigInt(a), Value::BigInt(b)) => Some(a.as_ref().cmp(b)),
+        (Value::BigInt(a), Value::Int(b)) => Some(a.as_ref().cmp(&BigInt::from(*b))),
+        (Value::Int(a), Value::BigInt(b)) => Some(BigInt::from(*a).cmp(b)),
+        (Value::Rational(_), _) | (_, Value::Rational(_)) => match (to_rational(left), to_rational(right)) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => left.as_f64()?.partial_cmp(&right.as_f64()?),
+        },
+        (left, right) => left.as_f64()?.partial_cmp(&right.as_f64()?),
+    }
+}
+
+// an int against a number without rounding the int to an f64 first, which would make 2^53 + 1 equal to 2^53
+// so EQ agrees with map keys, where a whole number is the same key as the int it's equal to
+fn int_number_cmp(a: i64, b: OperandSize) -> Option<Ordering> {
+    if b.is_nan() {
+        return None;
+    }
+
+    // 2^63 is exactly an f64, and every number from there up (or below -2^63) is past any int
+    if b >= 9223372036854775808.0 {
+        return Some(Ordering::Less);
+    }
+    if b < -9223372036854775808.0 {
+        return Some(Ordering::Greater);
+    }
+
+    let whole = b.trunc();
+    match a.cmp(&(whole as i64)) {
+        Ordering::Equal => 0.0.partial_cmp(&(b - whole)),
+        ordering => Some(ordering),
+    }
+}
+
+// division truncates towards zero and MOD takes the sign of the left, the same as rust's / and %
+// dividing by zero is an error whatever the mode, there's nothing sensible to wrap or saturate to
+pub fn int_arithmetic(op: ArithmeticOp, a: i64, b: i64, mode: OverflowMode) -> Result<i64, VMError> {
+    if b == 0 && matches!(op, ArithmeticOp::Div | ArithmeticOp::Mod) {
+        return Err(VMError::DivisionByZero);
+    }
+
+    let checked = match op {
+        ArithmeticOp::Add => a.checked_add(b),
+        ArithmeticOp::Sub => a.checked_sub(b),
+        ArithmeticOp::Mul => a.checked_mul(b),
+        ArithmeticOp::Div => a.checked_div(b),
+        ArithmeticOp::Mod => a.checked_rem(b),
+    };
+
+    if let Some(result) = checked {
+        return Ok(result);
+    }
+
+    match (mode, op) {
+        // only i64::MIN % -1 overflows, and the answer's 0 whatever the mode since that fits
+        (_, ArithmeticOp::Mod) => Ok(0),
+        (OverflowMode::Checked | OverflowMode::Promote, _) => Err(VMError::IntegerOverflow(op.name())),
+        (OverflowMode::Wrapping, ArithmeticOp::Add) => Ok(a.wrapping_add(b)),
+        (OverflowMode::Wrapping, ArithmeticOp::Sub) => Ok(a.wrapping_sub(b)),
+        (OverflowMode::Wrapping, ArithmeticOp::Mul) => Ok(a.wrapping_mul(b)),
+        (OverflowMode::Wrapping, ArithmeticOp::Div) => Ok(a.wrapping_div(b)),
+        (OverflowMode::Saturating, ArithmeticOp::Add) => Ok(a.saturating_add(b)),
+        (OverflowMode::Saturating, ArithmeticOp::Sub) => Ok(a.saturating_sub(b)),
+        (OverflowMode::Saturating, ArithmeticOp::Mul) => Ok(a.saturating_mul(b)),
+        (OverflowMode::Saturating, ArithmeticOp::Div) => Ok(a.saturating_div(b)),
+    }
+}
+
+// numbers are left to ieee, so dividing by zero gives inf or nan
+pub fn number_arithmetic(op: ArithmeticOp, a: OperandSize, b: OperandSize) -> OperandSize {
+    match op {
+        ArithmeticOp::Add => a + b,
+        ArithmeticOp::Sub => a - b,
+        ArithmeticOp::Mul => a * b,
+        ArithmeticOp::Div => a / b,
+        ArithmeticOp::Mod => a % b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [OverflowMode; 4] = [OverflowMode::Wrapping, OverflowMode::Checked, OverflowMode::Saturating, OverflowMode::Promote];
+
+    fn overflowed(result: Result<i64, VMError>, instruction: &str) -> bool {
+        matches!(result, Err(VMError::IntegerOverflow(name)) if name == instruction)
+    }
+
+    #[test]
+    fn checked_is_the_default() {
+        assert_eq!(OverflowMode::default(), OverflowMode::Checked);
+    }
+
+    #[test]
+    fn checked_raises_on_overflow() {
+        let mode = OverflowMode::Checked;
+
+        assert!(overflowed(int_arithmetic(ArithmeticOp::Add, i64::MAX, 1, mode), "ADD"));
+        assert!(overflowed(int_arithmetic(ArithmeticOp::Sub, i64::MIN, 1, mode), "SUB"));
+        assert!(overflowed(int_arithmetic(ArithmeticOp::Mul, i64::MAX, 2, mode), "MUL"));
+        assert!(overflowed(int_arithmetic(ArithmeticOp::Div, i64::MIN, -1, mode), "DIV"));
+
+        assert_eq!(int_arithmetic(ArithmeticOp::Add, i64::MAX - 1, 1, mode).unwrap(), i64::MAX);
+        assert_eq!(int_arithmetic(ArithmeticOp::Sub, i64::MIN + 1, 1, mode).unwrap(), i64::MIN);
+        assert_eq!(int_arithmetic(ArithmeticOp::Mul, -3, 4, mode).unwrap(), -12);
+    }
+
+    #[test]
+    fn wrapping_wraps_around() {
+        let mode = OverflowMode::Wrapping;
+
+        assert_eq!(int_arithmetic(ArithmeticOp::Add, i64::MAX, 1, mode).unwrap(), i64::MIN);
+        assert_eq!(int_arithmetic(ArithmeticOp::Sub, i64::MIN, 1, mode).unwrap(), i64::MAX);
+        assert_eq!(int_arithmetic(ArithmeticOp::Mul, i64::MAX, 2, mode).unwrap(), -2);
+        assert_eq!(int_arithmetic(ArithmeticOp::Div, i64::MIN, -1, mode).unwrap(), i64::MIN);
+    }
+
+    #[test]
+    fn saturating_sticks_at_the_limits() {
+        let mode = OverflowMode::Saturating;
+
+        assert_eq!(int_arithmetic(ArithmeticOp::Add, i64::MAX, 1, mode).unwrap(), i64::MAX);
+        assert_eq!(int_arithmetic(ArithmeticOp::Sub, i64::MIN, 1, mode).unwrap(), i64::MIN);
+        assert_eq!(int_arithmetic(ArithmeticOp::Mul, i64::MAX, 2, mode).unwrap(), i64::MAX);
+        assert_eq!(int_arithmetic(ArithmeticOp::Mul, i64::MIN, 2, mode).unwrap(), i64::MIN);
+        assert_eq!(int_arithmetic(ArithmeticOp::Div, i64::MIN, -1, mode).unwrap(), i64::MAX);
+    }
+
+    #[test]
+    fn promote_carries_on_as_a_bigint() {
+        let result = arithmetic(ArithmeticOp::Add, Value::Int(i64::MAX), Value::Int(1), OverflowMode::Promote).unwrap();
+        assert_eq!(format!("{:?}", result), "BigInt(9223372036854775808)");
+
+        // and comes back to an int once it fits again
+        let result = arithmetic(ArithmeticOp::Sub, result, Value::Int(1), OverflowMode::Promote).unwrap();
+        assert!(matches!(result, Value::Int(i64::MAX)));
+    }
+
+    #[test]
+    fn min_mod_minus_one_is_zero_in_every_mode() {
+        for mode in MODES {
+            assert_eq!(int_arithmetic(ArithmeticOp::Mod, i64::MIN, -1, mode).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn ints_and_numbers_compare_exactly() {
+        let cmp = |a: i64, b: OperandSize| numeric_cmp(&Value::Int(a), &Value::Number(b));
+
+        assert_eq!(cmp((1 << 53) + 1, (1u64 << 53) as OperandSize), Some(Ordering::Greater));
+        assert_eq!(cmp(1 << 53, (1u64 << 53) as OperandSize), Some(Ordering::Equal));
+        assert_eq!(cmp(i64::MAX, 9223372036854775808.0), Some(Ordering::Less));
+        assert_eq!(cmp(i64::MIN, -9223372036854775808.0), Some(Ordering::Equal));
+        assert_eq!(cmp(2, 2.5), Some(Ordering::Less));
+        assert_eq!(cmp(-2, -2.5), Some(Ordering::Greater));
+        assert_eq!(cmp(0, -0.0), Some(Ordering::Equal));
+        assert_eq!(cmp(5, OperandSize::NAN), None);
+        assert_eq!(cmp(5, OperandSize::NEG_INFINITY), Some(Ordering::Greater));
+        assert_eq!(numeric_cmp(&Value::Number(2.5), &Value::Int(2)), Some(Ordering::Greater));
+    }
+
+    #[test]
+    fn dividing_by_zero_is_an_error_in_every_mode() {
+        for mode in MODES {
+            assert!(matches!(int_arithmetic(ArithmeticOp::Div, 1, 0, mode), Err(VMError::DivisionByZero)));
+            assert!(matches!(int_arithmetic(ArithmeticOp::Mod, 1, 0, mode), Err(VMError::DivisionByZero)));
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use crate::lsm::bytecode::{to_uint, Export, Import, Object, MAX_CONST_DEPTH};
+use crate::lsm::debug::{DebugInfo, DebugSymbol, SourceRange};
+use crate::lsm::instruction::{Instruction, OperandKind, RawInstruction};
+use crate::lsm::bigint::BigInt;
+use crate::lsm::bytes::Bytes;
+use crate::lsm::intern::intern;
+use crate::lsm::list::List;
+use crate::lsm::shared::Shared;
+use crate::lsm::vm::{OperandSize, Value};
+
+/*
+assembly source for reference, one statement per line and ; starts a comment
+
+.const <name> <value>   - adds a const, value is a number, "string", true, false, nil, 0x and hex bytes or a [list of values] separated by spaces
+.export <label>         - makes the label visible to other objects when linking
+.import <name>          - a label another object exports, usable anywhere an address is
+<label>:                - names the address of the next instruction
+<MNEMONIC> [operand]    - an instruction, addresses can be labels and const keys can be const names
+ */
+
+#[derive(Clone, Debug)]
+pub struct AsmError {
+    pub line: usize, // 1 based
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+}
+
+// an instruction waiting on labels to be resolved
+struct Statement<'a> {
+    line: usize,
+    column: usize,
+    instruction: &'a Instruction,
+    operand: Option<Token>,
+}
+
+// assembles source into an object, which can be loaded straight away if it has no imports
+// the file name only ends up in the debug info, so errors and traces can point back at the source
+pub fn assemble(source: &str, file: &str, instruction_set: &[Instruction]) -> Result<Object, AsmError> {
+    let mut object = Object::default();
+    let mut statements = Vec::new();
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut consts: HashMap<String, usize> = HashMap::new();
+    let mut const_keys: HashMap<ConstKey, usize> = HashMap::new(); // where each distinct value ended up
+    let mut exports: Vec<(usize, String)> = Vec::new();
+    let mut imports: Vec<(usize, String)> = Vec::new();
+
+    // first pass works out where every label is
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| AsmError { line, message };
+
+        let mut tokens = tokenize(text).map_err(error)?;
+
+        // any amount of labels can come before the statement
+        while let Some((_, Token::Word(word))) = tokens.first() {
+            let Some(label) = word.strip_suffix(':') else {
+                break;
+            };
+
+            if labels.insert(label.to_string(), statements.len()).is_some() {
+                return Err(error(format!("label {} is already defined", label)));
+            }
+            tokens.remove(0);
+        }
+
+        let mut tokens = tokens.into_iter();
+        let Some((column, first)) = tokens.next() else {
+            continue;
+        };
+        let mut tokens = tokens.map(|(_, token)| token).peekable();
+
+        let Token::Word(word) = first else {
+            return Err(error("expected an instruction or directive".to_string()));
+        };
+
+        match word.as_str() {
+            ".const" => {
+                let Some(Token::Word(name)) = tokens.next() else {
+                    return Err(error("expected .const <name> <value>".to_string()));
+                };
+
+                // identical values share a slot, whatever they're called
+                let value = parse_const(&mut tokens, 0).map_err(error)?;
+                if tokens.next().is_some() {
+                    return Err(error("expected .const <name> <value>".to_string()));
+                }
+                let key = match ConstKey::new(&value) {
+                    Some(key) => *const_keys.entry(key).or_insert_with(|| {
+                        object.consts.push(value);
+                        object.consts.len() - 1
+                    }),
+                    None => {
+                        object.consts.push(value);
+                        object.consts.len() - 1
+                    }
+                };
+
+                if consts.insert(name.clone(), key).is_some() {
+                    return Err(error(format!("const {} is already defined", name)));
+                }
+            }
+            ".export" => {
+                let (Some(Token::Word(name)), None) = (tokens.next(), tokens.next()) else {
+                    return Err(error("expected .export <label>".to_string()));
+                };
+
+                exports.push((line, name));
+            }
+            ".import" => {
+                let (Some(Token::Word(name)), None) = (tokens.next(), tokens.next()) else {
+                    return Err(error("expected .import <name>".to_string()));
+                };
+
+                if !imports.iter().any(|(_, import)| *import == name) {
+                    imports.push((line, name));
+                }
+            }
+            mnemonic => {
+                let instruction = instruction_set.iter()
+                    .find(|instruction| instruction.name.eq_ignore_ascii_case(mnemonic))
+                    .ok_or_else(|| error(format!("unknown instruction {}", mnemonic)))?;
+
+                let operand = tokens.next();
+
+                if tokens.next().is_some() {
+                    return Err(error(format!("too many operands for {}", instruction.name)));
+                }
+
+                match (instruction.requires_operand(), &operand) {
+                    (true, None) => return Err(error(format!("{} needs an operand", instruction.name))),
+                    (false, Some(_)) => return Err(error(format!("{} doesn't take an operand", instruction.name))),
+                    _ => {}
+                }
+
+                statements.push(Statement { line, column, instruction, operand });
+            }
+        }
+    }
+
+    for (line, name) in imports.iter() {
+        if labels.contains_key(name) {
+            return Err(AsmError { line: *line, message: format!("{} is both imported and defined here", name) });
+        }
+    }
+
+    // second pass now every label is known
+    let mut references: HashMap<&str, Vec<usize>> = HashMap::new();
+
+    for (address, statement) in statements.iter().enumerate() {
+        let error = |message: String| AsmError { line: statement.line, message };
+
+        let operand = match (&statement.operand, statement.instruction.operand) {
+            (None, _) => None,
+            (Some(Token::Str(_)), _) => return Err(error(format!("{} can't take a string, use a .const", statement.instruction.name))),
+            (Some(Token::Word(word)), kind) => {
+                if let Ok(number) = word.parse::<OperandSize>() {
+                    // an address or const key has to be a whole u32 for the bytecode to hold it
+                    match kind {
+                        OperandKind::Address if to_uint(number).is_none() => return Err(error(format!("{} isn't a valid address", word))),
+                        OperandKind::ConstKey if to_uint(number).is_none() => return Err(error(format!("{} isn't a valid const key", word))),
+                        _ => Some(number),
+                    }
+                } else {
+                    match kind {
+                        OperandKind::Address => {
+                            if let Some(target) = labels.get(word) {
+                                Some(*target as OperandSize)
+                            } else if let Some((_, name)) = imports.iter().find(|(_, name)| name == word) {
+                                // the linker fills this in
+                                references.entry(name).or_default().push(address);
+                                Some(0.0)
+                            } else {
+                                return Err(error(format!("unknown label {}", word)));
+                            }
+                        }
+                        OperandKind::ConstKey => match consts.get(word) {
+                            Some(key) => Some(*key as OperandSize),
+                            None => return Err(error(format!("unknown const {}", word))),
+                        },
+                        _ => return Err(error(format!("expected a number, found {}", word))),
+                    }
+                }
+            }
+        };
+
+        object.code.push(RawInstruction { opcode: statement.instruction.opcode, operand });
+    }
+
+    for (line, name) in exports {
+        match labels.get(&name) {
+            Some(address) => object.exports.push(Export { name, address: *address }),
+            None => return Err(AsmError { line, message: format!("can't export unknown label {}", name) }),
+        }
+    }
+
+    // every instruction came from its own line, and every label is worth a name in the disassembly
+    let mut debug = DebugInfo { files: vec![file.to_string()], ..DebugInfo::default() };
+
+    for (address, statement) in statements.iter().enumerate() {
+        debug.ranges.push(SourceRange { start: address, end: address + 1, file: 0, line: statement.line as u32, column: statement.column as u32 });
+    }
+
+    debug.symbols = labels.into_iter().map(|(name, address)| DebugSymbol { name, address }).collect();
+    debug.symbols.sort_by(|a, b| a.address.cmp(&b.address).then_with(|| a.name.cmp(&b.name)));
+    object.debug = Some(debug);
+
+    // imports nothing ends up using aren't worth keeping
+    for (_, name) in imports.iter() {
+        if let Some(references) = references.remove(name.as_str()) {
+            object.imports.push(Import { name: name.clone(), references });
+        }
+    }
+
+    Ok(object)
+}
+
+// a const as a hash key, two consts have the same key exactly when they're identical
+// None for anything that can't be a const, which parse_const never gives
+#[derive(PartialEq, Eq, Hash)]
+enum ConstKey {
+    Number(u64), // the bits, so nan is the same as itself and 0 isn't the same as -0
+    Int(i64),
+    BigInt(BigInt),
+    Str(Shared<String>),
+    Bool(bool),
+    Nil,
+    List(Vec<ConstKey>),
+    Bytes(Vec<u8>),
+}
+
+impl ConstKey {
+    fn new(value: &Value) -> Option<ConstKey> {
+        Some(match value {
+            Value::Number(n) => ConstKey::Number(n.to_bits()),
+            Value::Int(n) => ConstKey::Int(*n),
+            Value::BigInt(n) => ConstKey::BigInt(n.as_ref().clone()),
+            Value::Str(s) => ConstKey::Str(s.clone()),
+            Value::Bool(b) => ConstKey::Bool(*b),
+            Value::Nil => ConstKey::Nil,
+            Value::List(list) => ConstKey::List(list.to_vec().iter().map(ConstKey::new).collect::<Option<_>>()?),
+            Value::Bytes(bytes) => ConstKey::Bytes(bytes.to_vec()),
+            _ => return None,
+        })
+    }
+}
+
+// one value from the tokens, which for a list is everything up to its closing ]
+// depth is how many lists it's inside, which is limited so the bytecode can be loaded again
+fn parse_const<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>, depth: usize) -> Result<Value, String> {
+    match tokens.next() {
+        None => Err("expected .const <name> <value>".to_string()),
+        Some(Token::Str(s)) => Ok(Value::Str(intern(&s))),
+        Some(Token::Word(word)) => match word.as_str() {
+            "[" if depth >= MAX_CONST_DEPTH => Err(format!("lists can't be nested more than {} deep", MAX_CONST_DEPTH)),
+            "[" => {
+                let mut values = Vec::new();
+
+                loop {
+                    match tokens.peek() {
+                        Some(Token::Word(word)) if word == "]" => break,
+                        Some(_) => values.push(parse_const(tokens, depth + 1)?),
+                        None => return Err("list is missing its closing ]".to_string()),
+                    }
+                }
+                tokens.next();
+
+                Ok(Value::List(Shared::new(List::new(values))))
+            }
+            "]" => Err("unexpected ]".to_string()),
+            _ if word.starts_with("0x") => parse_bytes(&word[2..]).map(|bytes| Value::Bytes(Shared::new(Bytes::new(bytes)))).ok_or(format!("invalid const value {}", word)),
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            "nil" => Ok(Value::Nil),
+            // ints have an i on the end, since a plain 1 has always been a number, and anything too big for an i64 is a bigint
+            _ if word.ends_with('i') => word[..word.len() - 1].parse::<BigInt>().map(Value::from).map_err(|_| format!("invalid const value {}", word)),
+            _ => word.parse::<OperandSize>().map(Value::Number).map_err(|_| format!("invalid const value {}", word)),
+        },
+    }
+}
+
+// two hex digits a byte, None if there's an odd one out or something that isn't hex
+fn parse_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+// splits a line into words and quoted strings, stopping at a comment
+// every token comes with the 1 based column it starts at
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == ';' {
+            break;
+        } else if c == '[' || c == ']' {
+            // brackets are words of their own, so [1 2] is the same as [ 1 2 ]
+            tokens.push((column, Token::Word(c.to_string())));
+            i += 1;
+        } else if c == '"' {
+            i += 1;
+            let mut string = String::new();
+
+            loop {
+                let c = chars.get(i).copied();
+                i += 1;
+
+                match c {
+                    Some('"') => break,
+                    Some('\\') => {
+                        let escaped = chars.get(i).copied();
+                        i += 1;
+
+                        match escaped {
+                            Some('n') => string.push('\n'),
+                            Some('t') => string.push('\t'),
+                            Some('r') => string.push('\r'),
+                            Some('0') => string.push('\0'),
+                            Some(c @ ('"' | '\\')) => string.push(c),
+                            Some(c) => return Err(format!("unknown escape \\{}", c)),
+                            None => return Err("unterminated string".to_string()),
+                        }
+                    }
+                    Some(c) => string.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+
+            tokens.push((column, Token::Str(string)));
+        } else {
+            let mut word = String::new();
+
+            while let Some(&c) = chars.get(i) {
+                if c.is_whitespace() || matches!(c, ';' | '"' | '[' | ']') {
+                    break;
+                }
+                word.push(c);
+                i += 1;
+            }
+
+            tokens.push((column, Token::Word(word)));
+        }
+    }
+
+    Ok(tokens)
+}
@@ -0,0 +1,548 @@
+// arbitrary precision integers, for when an i64 isn't enough
+// a sign and a magnitude of 32 bit limbs, lowest first, with no zero limbs on the end so every value has one form
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Shl, Shr, Sub};
+use std::str::FromStr;
+
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool, // never set for zero
+    magnitude: Vec<u32>,
+}
+
+// the biggest power of 10 that fits in a limb, formatting and parsing work 9 digits at a time
+const DECIMAL_CHUNK: u32 = 1_000_000_000;
+const DECIMAL_CHUNK_DIGITS: usize = 9;
+
+impl BigInt {
+    fn new(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+
+        let negative = negative && !magnitude.is_empty();
+        BigInt { negative, magnitude }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    // the value as an i64, if it fits in one
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+
+        let n = self.magnitude.iter().rev().fold(0u64, |n, limb| (n << 32) | *limb as u64);
+
+        match self.negative {
+            false if n <= i64::MAX as u64 => Some(n as i64),
+            // i64::MIN's magnitude is one more than i64::MAX's, and wraps round to itself
+            true if n <= 1 << 63 => Some((n as i64).wrapping_neg()),
+            _ => None,
+        }
+    }
+
+    // as close as an f64 gets, or an infinity if it's too big for one
+    pub fn to_f64(&self) -> f64 {
+        let n = self.magnitude.iter().rev().fold(0.0, |n, limb| n * 4294967296.0 + *limb as f64);
+        if self.negative { -n } else { n }
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt { negative: false, magnitude: self.magnitude.clone() }
+    }
+
+    // how many bits the magnitude needs, 0 for zero
+    pub fn bits(&self) -> usize {
+        match self.magnitude.last() {
+            Some(top) => self.magnitude.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    // the greatest common divisor, which is never negative, and is 0 only when both are
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut a, mut b) = (self.abs(), other.abs());
+
+        while !b.is_zero() {
+            let (_, remainder) = divmod_magnitudes(&a.magnitude, &b.magnitude);
+            a = b;
+            b = BigInt::new(false, remainder);
+        }
+
+        a
+    }
+
+    // the quotient, truncated towards zero, and the remainder, which takes the sign of self, the same as i64's / and %
+    // None when dividing by zero
+    pub fn divmod(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+
+        let (quotient, remainder) = divmod_magnitudes(&self.magnitude, &other.magnitude);
+        Some((BigInt::new(self.negative != other.negative, quotient), BigInt::new(self.negative, remainder)))
+    }
+
+    // the sign then the magnitude's bytes, lowest first, which is how bigint consts are stored
+    pub fn to_bytes(&self) -> (bool, Vec<u8>) {
+        let mut bytes: Vec<u8> = self.magnitude.iter().flat_map(|limb| limb.to_le_bytes()).collect();
+
+        while bytes.last() == Some(&0) {
+            bytes.pop();
+        }
+
+        (self.negative, bytes)
+    }
+
+    pub fn from_bytes(negative: bool, bytes: &[u8]) -> BigInt {
+        let magnitude = bytes.chunks(4).map(|chunk| {
+            let mut limb = [0; 4];
+            limb[..chunk.len()].copy_from_slice(chunk);
+            u32::from_le_bytes(limb)
+        }).collect();
+
+        BigInt::new(negative, magnitude)
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.magnitude.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_magnitudes(&self.magnitude, &other.magnitude));
+        }
+
+        // different signs, so it's the difference of the magnitudes with the sign of the bigger one
+        match compare_magnitudes(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::new(other.negative, sub_magnitudes(&other.magnitude, &self.magnitude)),
+            _ => BigInt::new(self.negative, sub_magnitudes(&self.magnitude, &other.magnitude)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::new(self.negative != other.negative, mul_magnitudes(&self.magnitude, &other.magnitude))
+    }
+}
+
+// shifts move the magnitude and leave the sign alone, so shifting right truncates towards zero
+impl Shl<usize> for &BigInt {
+    type Output = BigInt;
+
+    fn shl(self, bits: usize) -> BigInt {
+        let mut magnitude = vec![0u32; bits / 32];
+        let shift = bits % 32;
+        let mut carry = 0;
+
+        for limb in &self.magnitude {
+            magnitude.push(match shift {
+                0 => *limb,
+                _ => (*limb << shift) | carry,
+            });
+            carry = match shift {
+                0 => 0,
+                _ => *limb >> (32 - shift),
+            };
+        }
+        magnitude.push(carry);
+
+        BigInt::new(self.negative, magnitude)
+    }
+}
+
+impl Shr<usize> for &BigInt {
+    type Output = BigInt;
+
+    fn shr(self, bits: usize) -> BigInt {
+        let limbs = self.magnitude.get(bits / 32..).unwrap_or(&[]);
+        let shift = bits % 32;
+
+        let magnitude = (0..limbs.len()).map(|i| match shift {
+            0 => limbs[i],
+            _ => (limbs[i] >> shift) | (*limbs.get(i + 1).unwrap_or(&0) << (32 - shift)),
+        }).collect();
+
+        BigInt::new(self.negative, magnitude)
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> BigInt {
+        let magnitude = n.unsigned_abs();
+        BigInt::new(n < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitudes(&self.magnitude, &other.magnitude),
+            (true, true) => compare_magnitudes(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// in decimal
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // peel off 9 digits at a time from the bottom
+        let mut chunks = Vec::new();
+        let mut magnitude = self.magnitude.clone();
+
+        while !magnitude.is_empty() {
+            let (quotient, remainder) = divmod_small(&magnitude, DECIMAL_CHUNK);
+            chunks.push(remainder);
+            magnitude = quotient;
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+
+        // the top chunk has no leading zeroes, every other one is padded out to 9 digits
+        let mut chunks = chunks.iter().rev();
+        if let Some(top) = chunks.next() {
+            write!(f, "{}", top)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:0width$}", chunk, width = DECIMAL_CHUNK_DIGITS)?;
+        }
+
+        Ok(())
+    }
+}
+
+// the limbs don't mean much to anyone reading them, so it's the decimal form here too
+impl fmt::Debug for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseBigIntError;
+
+impl fmt::Display for ParseBigIntError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid integer")
+    }
+}
+
+// decimal digits, with a - in front if it's negative
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+
+    fn from_str(s: &str) -> Result<BigInt, ParseBigIntError> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+
+        if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(ParseBigIntError);
+        }
+
+        // 9 digits at a time, the first chunk taking whatever's left over so the rest are all 9
+        let mut magnitude = Vec::new();
+        let first = match digits.len() % DECIMAL_CHUNK_DIGITS {
+            0 => DECIMAL_CHUNK_DIGITS,
+            n => n,
+        };
+
+        let mut start = 0;
+        let mut end = first;
+        while start < digits.len() {
+            let chunk: u32 = digits[start..end].parse().map_err(|_| ParseBigIntError)?;
+            let scale = 10u32.pow((end - start) as u32);
+
+            magnitude = mul_add_small(&magnitude, scale, chunk);
+            start = end;
+            end += DECIMAL_CHUNK_DIGITS;
+        }
+
+        Ok(BigInt::new(negative, magnitude))
+    }
+}
+
+fn compare_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+
+    for i in 0..a.len().max(b.len()) {
+        let sum = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+
+    result
+}
+
+// a has to be at least as big as b
+fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+
+    for (i, limb) in a.iter().enumerate() {
+        let mut difference = *limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+
+        if difference < 0 {
+            difference += 1 << 32;
+            borrow = 1;
+        }
+
+        result.push(difference as u32);
+    }
+
+    trim(result)
+}
+
+// the schoolbook way, which is plenty for the sizes a program will see
+fn mul_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+
+    let mut result = vec![0u32; a.len() + b.len()];
+
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+
+        for (j, y) in b.iter().enumerate() {
+            let product = *x as u64 * *y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> 32;
+        }
+
+        result[i + b.len()] = carry as u32;
+    }
+
+    trim(result)
+}
+
+// a * scale + add, for parsing
+fn mul_add_small(a: &[u32], scale: u32, add: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len() + 1);
+    let mut carry = add as u64;
+
+    for limb in a {
+        let product = *limb as u64 * scale as u64 + carry;
+        result.push(product as u32);
+        carry = product >> 32;
+    }
+
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+
+    result
+}
+
+fn divmod_small(a: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0u64;
+
+    for i in (0..a.len()).rev() {
+        let current = (remainder << 32) | a[i] as u64;
+        quotient[i] = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+
+    (trim(quotient), remainder as u32)
+}
+
+// b can't be zero
+// one limb divisors get the quick way, anything bigger is done a bit at a time, which is slow but hard to get wrong
+fn divmod_magnitudes(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if compare_magnitudes(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+
+    if b.len() == 1 {
+        let (quotient, remainder) = divmod_small(a, b[0]);
+        return (quotient, trim(vec![remainder]));
+    }
+
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = Vec::with_capacity(b.len() + 1);
+
+    for bit in (0..a.len() * 32).rev() {
+        // remainder = remainder * 2 + the next bit of a
+        let mut carry = (a[bit / 32] >> (bit % 32)) & 1;
+        for limb in remainder.iter_mut() {
+            let top = *limb >> 31;
+            *limb = (*limb << 1) | carry;
+            carry = top;
+        }
+        if carry > 0 {
+            remainder.push(carry);
+        }
+
+        if compare_magnitudes(&remainder, b) != Ordering::Less {
+            remainder = sub_magnitudes(&remainder, b);
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+
+    (trim(quotient), trim(remainder))
+}
+
+fn trim(mut magnitude: Vec<u32>) -> Vec<u32> {
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+
+    magnitude
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // both sides of every limb boundary an i64 and an i128 can tell us about
+    const EDGES: [i128; 21] = [
+        0,
+        1,
+        -1,
+        2,
+        -7,
+        i64::MAX as i128,
+        i64::MIN as i128,
+        i64::MAX as i128 + 1,
+        i64::MIN as i128 - 1,
+        (1 << 32) - 1,
+        1 << 32,
+        (1 << 32) + 1,
+        -(1 << 32),
+        (1 << 64) - 1,
+        1 << 64,
+        (1 << 64) + 1,
+        -(1 << 64),
+        -(1 << 64) - 1,
+        1 << 95,
+        -(1 << 95),
+        123_456_789_012_345_678_901_234_567,
+    ];
+
+    fn big(n: i128) -> BigInt {
+        n.to_string().parse().unwrap()
+    }
+
+    fn same(big: BigInt, n: i128) {
+        assert_eq!(big.to_string(), n.to_string());
+        assert_eq!(big.is_negative(), n < 0);
+    }
+
+    #[test]
+    fn add_sub_and_mul_match_i128() {
+        for a in EDGES {
+            for b in EDGES {
+                same(&big(a) + &big(b), a + b);
+                same(&big(a) - &big(b), a - b);
+                if let Some(product) = a.checked_mul(b) {
+                    same(&big(a) * &big(b), product);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn divmod_truncates_like_i128() {
+        for a in EDGES {
+            for b in EDGES {
+                match big(a).divmod(&big(b)) {
+                    Some((quotient, remainder)) => {
+                        same(quotient, a / b);
+                        same(remainder, a % b);
+                    }
+                    None => assert_eq!(b, 0),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cmp_matches_i128() {
+        for a in EDGES {
+            for b in EDGES {
+                assert_eq!(big(a).cmp(&big(b)), a.cmp(&b), "{} {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn display_and_from_str_round_trip() {
+        for n in EDGES {
+            same(big(n), n);
+            let (negative, bytes) = big(n).to_bytes();
+            same(BigInt::from_bytes(negative, &bytes), n);
+        }
+
+        same("+42".parse().unwrap(), 42);
+        same("-0".parse().unwrap(), 0);
+        same("000000000000000000001".parse().unwrap(), 1);
+
+        for bad in ["", "-", "+", "1a", " 1", "--1", "1.0"] {
+            assert_eq!(bad.parse::<BigInt>(), Err(ParseBigIntError), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn to_i64_only_gives_what_fits() {
+        for n in EDGES {
+            assert_eq!(big(n).to_i64(), i64::try_from(n).ok(), "{}", n);
+        }
+
+        assert_eq!(big(i64::MIN as i128).to_i64(), Some(i64::MIN));
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        same(BigInt::from(i64::MIN), i64::MIN as i128);
+    }
+}
@@ -0,0 +1,1299 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{ErrorKind, Read};
+use crate::lsm::debug::{DebugInfo, DebugSymbol, SourceRange};
+use crate::lsm::instruction::{Instruction, OpcodeSize, OperandKind, RawInstruction};
+use crate::lsm::bigint::BigInt;
+use crate::lsm::bytes::Bytes;
+use crate::lsm::intern::intern_string;
+use crate::lsm::list::List;
+use crate::lsm::shared::Shared;
+use crate::lsm::vm::{OperandSize, Value};
+
+pub const BYTECODE_SIGNATURE: &str = "!LSM!";
+const BYTECODE_CONSTS_SIGNATURE: &str = "!CONSTS";
+const BYTECODE_SYMBOLS_SIGNATURE: &str = "!SYMBOLS";
+const BYTECODE_DEBUG_SIGNATURE: &str = "!DEBUG";
+const BYTECODE_INSTRUCTIONS_SIGNATURE: &str = "!INSTR";
+
+// the original format, which has no version byte and stores every number as a u32 and every operand as an f64
+pub const BYTECODE_VERSION_1: u8 = 1;
+// a version byte after the signature, then counts, lengths, addresses and const keys as varints
+// only immediate operands (e.g. PUSH) are still a full f64
+pub const BYTECODE_VERSION_2: u8 = 2;
+// numbers the same as version 2, but the header has flags and a table of sections rather than signatures between them
+pub const BYTECODE_VERSION_3: u8 = 3;
+pub const LATEST_BYTECODE_VERSION: u8 = BYTECODE_VERSION_3;
+
+// header flags, loaders ignore any they don't know about
+pub const FLAG_NEEDS_LINKING: u8 = 1; // there are imports, so it's an object rather than a program
+pub const FLAG_CHECKSUMS: u8 = 2; // every entry in the section table has a crc32 of the section after its length
+
+// section kinds in the section table, a loader skips any kind it doesn't know about
+pub const SECTION_CUSTOM: u8 = 0; // a name then whatever the tool that added it wants
+pub const SECTION_CONSTS: u8 = 1;
+pub const SECTION_SYMBOLS: u8 = 2;
+pub const SECTION_DEBUG: u8 = 3;
+pub const SECTION_CODE: u8 = 4;
+
+// type bytes in the constants section
+const CONST_NUMBER: u8 = 1;
+const CONST_STR: u8 = 2;
+const CONST_BOOL: u8 = 3;
+const CONST_NIL: u8 = 4;
+const CONST_INT: u8 = 5;
+const CONST_BIGINT: u8 = 6; // a sign byte then the magnitude's bytes, lowest first, as a length and bytes like a string
+const CONST_LIST: u8 = 7; // a count then that many consts, each with its own type byte
+const CONST_BYTES: u8 = 8; // a length then the bytes, like a string that doesn't have to be utf8
+pub const MAX_CONST_DEPTH: usize = 64; // how many lists deep a const can be
+
+// kind bytes in the symbols section
+const SYMBOL_EXPORT: u8 = 1;
+const SYMBOL_IMPORT: u8 = 2;
+
+// a symbol this object provides for others to use
+#[derive(Clone, Debug)]
+pub struct Export {
+    pub name: String,
+    pub address: usize,
+}
+
+// a symbol this object uses but another object has to provide
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub name: String,
+    pub references: Vec<usize>, // addresses of the instructions whose operand is this symbol
+}
+
+// decoded bytecode, either a whole program or an object that still needs linking
+#[derive(Clone, Debug, Default)]
+pub struct Object {
+    pub consts: Vec<Value>,
+    pub code: Vec<RawInstruction>,
+    pub exports: Vec<Export>,
+    pub imports: Vec<Import>,
+    pub debug: Option<DebugInfo>, // only there if whatever produced the bytecode emitted it
+    pub custom_sections: Vec<CustomSection>, // only kept by version 3 and up
+}
+
+// a section added by some other tool, carried along untouched
+#[derive(Clone, Debug)]
+pub struct CustomSection {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+// what's wrong with some bytecode, every offset is from the start of it
+#[derive(Clone, Debug)]
+pub enum BytecodeError {
+    InvalidSignature,
+    UnsupportedVersion(u8),
+    UnexpectedEnd { offset: usize }, // it stops part way through something
+    Unrecognized { offset: usize }, // bytes that aren't the start of any section
+    InvalidString { offset: usize },
+    InvalidVarint { offset: usize },
+    IllegalInstruction { opcode: OpcodeSize, offset: usize },
+    SectionLengthMismatch { kind: u8, offset: usize }, // what's in it ends before the section does
+    ChecksumMismatch { kind: u8, offset: usize, expected: u32, found: u32 },
+    Io { offset: usize, message: String }, // reading from the source failed
+    UnresolvedImport(String), // fine as an object, but it can't be loaded until it's linked
+    ConstTooDeep { offset: usize }, // lists inside lists past MAX_CONST_DEPTH
+    InvalidOperand { address: usize, operand: OperandSize }, // an address or const key that can't be encoded, since it isn't a whole u32
+    InvalidConst(&'static str), // a const of a type that can't be encoded, e.g. a map
+}
+
+impl BytecodeError {
+    // where the first malformed byte is, if it's down to a particular byte
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            BytecodeError::InvalidSignature => Some(0),
+            BytecodeError::UnsupportedVersion(_) => Some(BYTECODE_SIGNATURE.len()),
+            BytecodeError::UnexpectedEnd { offset }
+            | BytecodeError::Unrecognized { offset }
+            | BytecodeError::InvalidString { offset }
+            | BytecodeError::InvalidVarint { offset }
+            | BytecodeError::IllegalInstruction { offset, .. }
+            | BytecodeError::SectionLengthMismatch { offset, .. }
+            | BytecodeError::ChecksumMismatch { offset, .. }
+            | BytecodeError::Io { offset, .. }
+            | BytecodeError::ConstTooDeep { offset } => Some(*offset),
+            BytecodeError::UnresolvedImport(_) | BytecodeError::InvalidOperand { .. } | BytecodeError::InvalidConst(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::InvalidSignature => write!(f, "not bytecode, it doesn't start with {}", BYTECODE_SIGNATURE),
+            BytecodeError::UnsupportedVersion(version) => write!(f, "unsupported bytecode version {}", version),
+            BytecodeError::UnexpectedEnd { offset } => write!(f, "unexpected end of bytecode at offset {}", offset),
+            BytecodeError::Unrecognized { offset } => write!(f, "unrecognized bytes at offset {}", offset),
+            BytecodeError::InvalidString { offset } => write!(f, "invalid utf8 string at offset {}", offset),
+            BytecodeError::InvalidVarint { offset } => write!(f, "invalid varint at offset {}", offset),
+            BytecodeError::IllegalInstruction { opcode, offset } => write!(f, "illegal instruction {} at offset {}", opcode, offset),
+            BytecodeError::SectionLengthMismatch { kind, offset } => write!(f, "{} section ends early at offset {}", section_name(*kind), offset),
+            BytecodeError::ChecksumMismatch { kind, offset, expected, found } => {
+                write!(f, "{} section at offset {} has checksum {:08x}, expected {:08x}", section_name(*kind), offset, found, expected)
+            }
+            BytecodeError::Io { offset, message } => write!(f, "couldn't read bytecode at offset {}: {}", offset, message),
+            BytecodeError::UnresolvedImport(name) => write!(f, "unresolved import {}, the object needs linking first", name),
+            BytecodeError::ConstTooDeep { offset } => write!(f, "const at offset {} is nested more than {} lists deep", offset, MAX_CONST_DEPTH),
+            BytecodeError::InvalidOperand { address, operand } => write!(f, "operand {} of the instruction at address {} isn't a valid address or const key", operand, address),
+            BytecodeError::InvalidConst(kind) => write!(f, "a {} can't be stored as a const", kind),
+        }
+    }
+}
+
+impl Error for BytecodeError {}
+
+// where a section is and whether it's intact, versions before 3 have no section table but their sections are reported the same way
+#[derive(Clone, Debug)]
+pub struct SectionInfo {
+    pub kind: u8,
+    pub offset: usize, // where its contents start
+    pub length: usize,
+    pub checksum: Checksum,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    NotStored, // versions before 3, or written without FLAG_CHECKSUMS
+    Unchecked, // decoding stopped before the end of the section, so it couldn't be checked
+    Valid(u32),
+    Invalid { expected: u32, found: u32 },
+}
+
+// everything inspect could work out about some bytecode, up to the first thing wrong with it
+#[derive(Clone, Debug, Default)]
+pub struct Inspection {
+    pub size: usize,
+    pub version: Option<u8>,
+    pub flags: Option<u8>, // only version 3 and up have flags
+    pub sections: Vec<SectionInfo>,
+    pub error: Option<BytecodeError>,
+}
+
+// e.g. consts, or unknown for a kind we don't know about
+pub fn section_name(kind: u8) -> &'static str {
+    match kind {
+        SECTION_CUSTOM => "custom",
+        SECTION_CONSTS => "consts",
+        SECTION_SYMBOLS => "symbols",
+        SECTION_DEBUG => "debug",
+        SECTION_CODE => "code",
+        _ => "unknown",
+    }
+}
+
+impl Object {
+    // decodes bytecode of any version, the instruction set is needed to know which opcodes are followed by an operand
+    pub fn decode(bytecode: &[u8], instruction_set: &[Instruction]) -> Result<Object, BytecodeError> {
+        Object::from_reader(bytecode, instruction_set)
+    }
+
+    // decodes bytecode as it's read, only ever buffering a little of it at a time
+    pub fn from_reader(source: impl Read, instruction_set: &[Instruction]) -> Result<Object, BytecodeError> {
+        decode(Reader::new(source), instruction_set, &mut Inspection::default())
+    }
+
+    // decodes as much as it can, reporting the header, every section and the first thing wrong rather than stopping at it
+    pub fn inspect(bytecode: &[u8], instruction_set: &[Instruction]) -> Inspection {
+        let mut inspection = Inspection { size: bytecode.len(), ..Inspection::default() };
+
+        if let Err(error) = decode(Reader::new(bytecode), instruction_set, &mut inspection) {
+            inspection.error = Some(error);
+        }
+
+        inspection
+    }
+
+    // encodes the object as the latest version
+    pub fn encode(&self, instruction_set: &[Instruction]) -> Result<Vec<u8>, BytecodeError> {
+        self.encode_version(LATEST_BYTECODE_VERSION, instruction_set)
+    }
+
+    // encodes the object back into bytecode of the given version, in the same layout decode reads
+    // from version 2 addresses and const keys are stored as whole numbers, and only version 3 keeps custom sections
+    // so an address or const key that isn't a whole u32 is an error there, rather than quietly becoming a different one
+    pub fn encode_version(&self, version: u8, instruction_set: &[Instruction]) -> Result<Vec<u8>, BytecodeError> {
+        let operands = operand_table(instruction_set);
+
+        let mut bytecode = Vec::new();
+        bytecode.extend_from_slice(BYTECODE_SIGNATURE.as_bytes());
+
+        if version == BYTECODE_VERSION_1 || version == BYTECODE_VERSION_2 {
+            let mut writer = Writer { bytes: bytecode, version };
+
+            if version == BYTECODE_VERSION_2 {
+                writer.u8(version);
+            }
+
+            if !self.consts.is_empty() {
+                writer.signature(BYTECODE_CONSTS_SIGNATURE);
+                self.write_consts(&mut writer)?;
+            }
+
+            if !self.exports.is_empty() || !self.imports.is_empty() {
+                writer.signature(BYTECODE_SYMBOLS_SIGNATURE);
+                self.write_symbols(&mut writer);
+            }
+
+            if let Some(debug) = &self.debug {
+                writer.signature(BYTECODE_DEBUG_SIGNATURE);
+                write_debug(&mut writer, debug);
+            }
+
+            writer.signature(BYTECODE_INSTRUCTIONS_SIGNATURE);
+            self.write_code(&mut writer, &operands)?;
+
+            return Ok(writer.bytes);
+        }
+
+        if version != BYTECODE_VERSION_3 {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+
+        // every section gets written on its own first, so we know how long they all are for the table
+        let mut sections: Vec<(u8, Vec<u8>)> = Vec::new();
+        let mut section = |kind: u8, write: &dyn Fn(&mut Writer) -> Result<(), BytecodeError>| {
+            let mut writer = Writer { bytes: Vec::new(), version };
+            write(&mut writer)?;
+            sections.push((kind, writer.bytes));
+            Ok::<(), BytecodeError>(())
+        };
+
+        if !self.consts.is_empty() {
+            section(SECTION_CONSTS, &|writer| self.write_consts(writer))?;
+        }
+
+        if !self.exports.is_empty() || !self.imports.is_empty() {
+            section(SECTION_SYMBOLS, &|writer| {
+                self.write_symbols(writer);
+                Ok(())
+            })?;
+        }
+
+        if let Some(debug) = &self.debug {
+            section(SECTION_DEBUG, &|writer| {
+                write_debug(writer, debug);
+                Ok(())
+            })?;
+        }
+
+        section(SECTION_CODE, &|writer| self.write_code(writer, &operands))?;
+
+        for custom in self.custom_sections.iter() {
+            section(SECTION_CUSTOM, &|writer| {
+                writer.string(&custom.name);
+                writer.bytes.extend_from_slice(&custom.data);
+                Ok(())
+            })?;
+        }
+
+        /*
+        header follows this pattern:
+        signature - version byte - flags byte - section count - table
+        where the table has a (kind byte - u32 length - u32 crc32) for every section, and the sections follow it in the same order
+         */
+        let mut writer = Writer { bytes: bytecode, version };
+        writer.u8(version);
+
+        let mut flags = FLAG_CHECKSUMS;
+        if !self.imports.is_empty() {
+            flags |= FLAG_NEEDS_LINKING;
+        }
+        writer.u8(flags);
+
+        writer.uint(sections.len() as u32);
+        for (kind, bytes) in sections.iter() {
+            writer.u8(*kind);
+            writer.bytes.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            writer.bytes.extend_from_slice(&crc32(bytes).to_le_bytes());
+        }
+
+        for (_, bytes) in sections.iter() {
+            writer.bytes.extend_from_slice(bytes);
+        }
+
+        Ok(writer.bytes)
+    }
+
+    fn write_consts(&self, writer: &mut Writer) -> Result<(), BytecodeError> {
+        for value in self.consts.iter() {
+            write_const(writer, value)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_symbols(&self, writer: &mut Writer) {
+        for export in self.exports.iter() {
+            writer.u8(SYMBOL_EXPORT);
+            writer.string(&export.name);
+            writer.uint(export.address as u32);
+        }
+
+        for import in self.imports.iter() {
+            writer.u8(SYMBOL_IMPORT);
+            writer.string(&import.name);
+            writer.uint(import.references.len() as u32);
+
+            for reference in import.references.iter() {
+                writer.uint(*reference as u32);
+            }
+        }
+    }
+
+    fn write_code(&self, writer: &mut Writer, operands: &OperandTable) -> Result<(), BytecodeError> {
+        for (address, instruction) in self.code.iter().enumerate() {
+            writer.u8(instruction.opcode);
+
+            if let Some(operand) = instruction.operand {
+                // an opcode we don't know can only have come with an operand the old way
+                let kind = operands[instruction.opcode as usize].unwrap_or(OperandKind::Immediate);
+                if !writer.operand(kind, operand) {
+                    return Err(BytecodeError::InvalidOperand { address, operand });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn decode<R: Read>(mut reader: Reader<R>, instruction_set: &[Instruction], inspection: &mut Inspection) -> Result<Object, BytecodeError> {
+    // check the signature at the top
+    if !reader.skip_signature(BYTECODE_SIGNATURE)? {
+        return Err(BytecodeError::InvalidSignature);
+    }
+
+    // version 1 goes straight into the first section's signature, which starts with a !
+    if reader.peek(1)?.first().is_some_and(|byte| *byte != b'!') {
+        reader.version = reader.u8()?;
+    }
+    inspection.version = Some(reader.version);
+
+    match reader.version {
+        BYTECODE_VERSION_1 | BYTECODE_VERSION_2 => decode_signatures(reader, instruction_set, inspection),
+        BYTECODE_VERSION_3 => decode_sections(reader, instruction_set, inspection),
+        version => Err(BytecodeError::UnsupportedVersion(version)),
+    }
+}
+
+// versions 1 and 2 find their sections by looking for each one's signature after the last
+fn decode_signatures<R: Read>(mut reader: Reader<R>, instruction_set: &[Instruction], inspection: &mut Inspection) -> Result<Object, BytecodeError> {
+    let mut object = Object::default();
+
+    // there's no table, so the sections are recorded as they're found
+    let mut section = |reader: &mut Reader<R>, kind: u8, read: &mut dyn FnMut(&mut Reader<R>) -> Result<(), BytecodeError>| {
+        let offset = reader.offset;
+        let result = read(reader);
+        inspection.sections.push(SectionInfo { kind, offset, length: reader.offset - offset, checksum: Checksum::NotStored });
+        result
+    };
+
+    if reader.skip_signature(BYTECODE_CONSTS_SIGNATURE)? {
+        section(&mut reader, SECTION_CONSTS, &mut |reader| read_consts(reader, &mut object))?;
+    }
+
+    // then the symbols, which only objects that need linking have
+    if reader.skip_signature(BYTECODE_SYMBOLS_SIGNATURE)? {
+        section(&mut reader, SECTION_SYMBOLS, &mut |reader| read_symbols(reader, &mut object))?;
+    }
+
+    // then the debug info, running the code doesn't need it but error messages and tools do
+    if reader.skip_signature(BYTECODE_DEBUG_SIGNATURE)? {
+        section(&mut reader, SECTION_DEBUG, &mut |reader| {
+            object.debug = Some(read_debug(reader)?);
+            Ok(())
+        })?;
+    }
+
+    // and now we probably want to check there's an instructions signature
+    if reader.skip_signature(BYTECODE_INSTRUCTIONS_SIGNATURE)? {
+        let operands = operand_table(instruction_set);
+        section(&mut reader, SECTION_CODE, &mut |reader| read_code(reader, &operands, &mut object))?;
+    }
+
+    // anything left is either something we don't know about or a section cut off part way through its signature
+    if !reader.at_end()? {
+        return Err(BytecodeError::Unrecognized { offset: reader.offset });
+    }
+
+    Ok(object)
+}
+
+// version 3 says up front where every section is and how long it is
+fn decode_sections<R: Read>(mut reader: Reader<R>, instruction_set: &[Instruction], inspection: &mut Inspection) -> Result<Object, BytecodeError> {
+    let mut object = Object::default();
+    let operands = operand_table(instruction_set);
+
+    // no flag changes how the sections are read, they're mostly there for tools
+    let flags = reader.u8()?;
+    inspection.flags = Some(flags);
+
+    let count = reader.uint()?;
+    let mut table = Vec::new();
+
+    for _ in 0..count {
+        let kind = reader.u8()?;
+        let length = reader.u32()? as usize;
+
+        let expected = match flags & FLAG_CHECKSUMS {
+            0 => None,
+            _ => Some(reader.u32()?),
+        };
+
+        table.push((kind, length, expected));
+    }
+
+    // the table says where everything is before any of it has been read
+    let mut offset = reader.offset;
+
+    for (kind, length, expected) in table.iter() {
+        let checksum = if expected.is_some() { Checksum::Unchecked } else { Checksum::NotStored };
+        inspection.sections.push(SectionInfo { kind: *kind, offset, length: *length, checksum });
+        offset = offset.saturating_add(*length);
+    }
+
+    for (index, (kind, length, expected)) in table.into_iter().enumerate() {
+        let start = reader.offset;
+        reader.end = Some(start.saturating_add(length));
+        reader.checksum = expected.map(|_| Crc32::new());
+
+        let result = read_section(&mut reader, kind, &operands, &mut object);
+
+        // the checksum covers the whole section, so a corrupted section gets reported as that rather than whatever it broke
+        let drained = reader.skip_to_end();
+
+        if let (Some(expected), Some(crc), Ok(())) = (expected, reader.checksum.take(), &drained) {
+            let found = crc.finish();
+
+            if found != expected {
+                inspection.sections[index].checksum = Checksum::Invalid { expected, found };
+                return Err(BytecodeError::ChecksumMismatch { kind, offset: start, expected, found });
+            }
+
+            inspection.sections[index].checksum = Checksum::Valid(found);
+        }
+
+        result?;
+        drained?;
+        reader.end = None;
+    }
+
+    // and nothing should come after the last section
+    if !reader.at_end()? {
+        return Err(BytecodeError::Unrecognized { offset: reader.offset });
+    }
+
+    Ok(object)
+}
+
+fn read_section<R: Read>(reader: &mut Reader<R>, kind: u8, operands: &OperandTable, object: &mut Object) -> Result<(), BytecodeError> {
+    match kind {
+        SECTION_CONSTS => read_consts(reader, object)?,
+        SECTION_SYMBOLS => read_symbols(reader, object)?,
+        SECTION_DEBUG => object.debug = Some(read_debug(reader)?),
+        SECTION_CODE => read_code(reader, operands, object)?,
+        SECTION_CUSTOM => {
+            let name = reader.string()?;
+            let data = reader.take_vec(reader.left_in_section())?;
+            object.custom_sections.push(CustomSection { name, data });
+        }
+        // something newer than us, which is fine to skip since we know how long it is
+        _ => return Ok(()),
+    }
+
+    // a section has to be exactly as long as the table says
+    if !reader.at_end()? {
+        return Err(BytecodeError::SectionLengthMismatch { kind, offset: reader.offset });
+    }
+
+    Ok(())
+}
+
+// a map, iterator or coroutine only exists at runtime, so it's an error rather than anything being written
+fn write_const(writer: &mut Writer, value: &Value) -> Result<(), BytecodeError> {
+    match value {
+        Value::Number(n) => {
+            writer.u8(CONST_NUMBER);
+            writer.f64(*n);
+        }
+        Value::Str(s) => {
+            writer.u8(CONST_STR);
+            writer.string(s);
+        }
+        Value::Bool(b) => {
+            writer.u8(CONST_BOOL);
+            writer.u8(*b as u8);
+        }
+        Value::Nil => {
+            writer.u8(CONST_NIL);
+        }
+        Value::Int(n) => {
+            writer.u8(CONST_INT);
+            writer.i64(*n);
+        }
+        Value::BigInt(n) => {
+            let (negative, bytes) = n.to_bytes();
+            writer.u8(CONST_BIGINT);
+            writer.u8(negative as u8);
+            writer.uint(bytes.len() as u32);
+            writer.bytes.extend_from_slice(&bytes);
+        }
+        Value::List(list) => {
+            let values = list.to_vec();
+            writer.u8(CONST_LIST);
+            writer.uint(values.len() as u32);
+
+            for value in values.iter() {
+                write_const(writer, value)?;
+            }
+        }
+        Value::Bytes(bytes) => {
+            let bytes = bytes.to_vec();
+            writer.u8(CONST_BYTES);
+            writer.uint(bytes.len() as u32);
+            writer.bytes.extend_from_slice(&bytes);
+        }
+        value => return Err(BytecodeError::InvalidConst(value.type_name())),
+    }
+
+    Ok(())
+}
+
+/*
+constant follows this pattern:
+type - data
+and therefore is this many bytes:
+1 byte - bytes based off of data type
+ */
+fn read_consts<R: Read>(reader: &mut Reader<R>, object: &mut Object) -> Result<(), BytecodeError> {
+    // every type byte where there's no match, we've reached the next signature
+    while let Some(&token) = reader.peek(1)?.first() {
+        if !(CONST_NUMBER..=CONST_BYTES).contains(&token) {
+            // not a type byte so the constants are done
+            break;
+        }
+
+        object.consts.push(read_const(reader, 0)?);
+    }
+
+    Ok(())
+}
+
+// one const, type byte and all, depth is how many lists it's inside
+fn read_const<R: Read>(reader: &mut Reader<R>, depth: usize) -> Result<Value, BytecodeError> {
+    let offset = reader.offset;
+
+    Ok(match reader.u8()? {
+        CONST_NUMBER => Value::Number(reader.f64()?),
+        CONST_STR => Value::Str(intern_string(reader.string()?)),
+        CONST_BOOL => Value::Bool(reader.u8()? != 0),
+        CONST_NIL => Value::Nil,
+        CONST_INT => Value::Int(reader.i64()?),
+        CONST_BIGINT => {
+            let negative = reader.u8()? != 0;
+            let length = reader.uint()? as usize;
+            Value::from(BigInt::from_bytes(negative, &reader.take_vec(length)?))
+        }
+        // nesting that deep is far more likely to be a bad file than a real program, and would blow the stack reading it
+        CONST_LIST if depth >= MAX_CONST_DEPTH => return Err(BytecodeError::ConstTooDeep { offset }),
+        CONST_LIST => {
+            // the count isn't trusted to size anything, a bad one runs out of bytes instead
+            let count = reader.uint()?;
+            let mut values = Vec::new();
+
+            for _ in 0..count {
+                values.push(read_const(reader, depth + 1)?);
+            }
+
+            Value::List(Shared::new(List::new(values)))
+        }
+        CONST_BYTES => {
+            let length = reader.uint()? as usize;
+            Value::Bytes(Shared::new(Bytes::new(reader.take_vec(length)?)))
+        }
+        _ => return Err(BytecodeError::Unrecognized { offset }),
+    })
+}
+
+/*
+symbol follows this pattern:
+kind - name - data
+where data is
+export: address
+import: count then that many addresses referencing it
+ */
+fn read_symbols<R: Read>(reader: &mut Reader<R>, object: &mut Object) -> Result<(), BytecodeError> {
+    while let Some(&kind) = reader.peek(1)?.first() {
+        if kind != SYMBOL_EXPORT && kind != SYMBOL_IMPORT {
+            break;
+        }
+
+        reader.u8()?;
+        let name = reader.string()?;
+
+        if kind == SYMBOL_EXPORT {
+            let address = reader.uint()? as usize;
+            object.exports.push(Export { name, address });
+        } else {
+            let count = reader.uint()?;
+            let mut references = Vec::new();
+
+            for _ in 0..count {
+                references.push(reader.uint()? as usize);
+            }
+
+            object.imports.push(Import { name, references });
+        }
+    }
+
+    Ok(())
+}
+
+/*
+debug info follows this pattern:
+file count - that many strings
+range count - that many (start address - end address - file index - line - column)
+symbol count - that many (name string - address)
+ */
+fn read_debug<R: Read>(reader: &mut Reader<R>) -> Result<DebugInfo, BytecodeError> {
+    let mut debug = DebugInfo::default();
+
+    let file_count = reader.uint()?;
+    for _ in 0..file_count {
+        debug.files.push(reader.string()?);
+    }
+
+    let range_count = reader.uint()?;
+    for _ in 0..range_count {
+        debug.ranges.push(SourceRange {
+            start: reader.uint()? as usize,
+            end: reader.uint()? as usize,
+            file: reader.uint()? as usize,
+            line: reader.uint()?,
+            column: reader.uint()?,
+        });
+    }
+
+    let symbol_count = reader.uint()?;
+    for _ in 0..symbol_count {
+        let name = reader.string()?;
+        let address = reader.uint()? as usize;
+        debug.symbols.push(DebugSymbol { name, address });
+    }
+
+    Ok(debug)
+}
+
+fn write_debug(writer: &mut Writer, debug: &DebugInfo) {
+    writer.uint(debug.files.len() as u32);
+    for file in debug.files.iter() {
+        writer.string(file);
+    }
+
+    writer.uint(debug.ranges.len() as u32);
+    for range in debug.ranges.iter() {
+        for n in [range.start as u32, range.end as u32, range.file as u32, range.line, range.column] {
+            writer.uint(n);
+        }
+    }
+
+    writer.uint(debug.symbols.len() as u32);
+    for symbol in debug.symbols.iter() {
+        writer.string(&symbol.name);
+        writer.uint(symbol.address as u32);
+    }
+}
+
+// every instruction is an OpcodeSize, followed by an operand if the instruction requires one
+// the code is always last, so it carries on until there's nothing left
+fn read_code<R: Read>(reader: &mut Reader<R>, operands: &OperandTable, object: &mut Object) -> Result<(), BytecodeError> {
+    while !reader.at_end()? {
+        let offset = reader.offset;
+        let opcode = reader.u8()? as OpcodeSize;
+
+        let operand = match operands[opcode as usize] {
+            Some(OperandKind::None) => None,
+            Some(kind) => Some(reader.operand(kind)?),
+            None => {
+                // no matching instruction </3
+                return Err(BytecodeError::IllegalInstruction { opcode, offset });
+            }
+        };
+
+        object.code.push(RawInstruction { opcode, operand });
+    }
+
+    Ok(())
+}
+
+// what kind of operand every opcode takes, None if there's no such instruction
+// looking the opcode up in a table is a lot quicker than searching the instruction set every time
+type OperandTable = [Option<OperandKind>; 1 << OpcodeSize::BITS];
+
+fn operand_table(instruction_set: &[Instruction]) -> OperandTable {
+    let mut operands = [None; 1 << OpcodeSize::BITS];
+
+    for instruction in instruction_set.iter() {
+        operands[instruction.opcode as usize] = Some(instruction.operand);
+    }
+
+    operands
+}
+
+// how much gets read from the source at once, which is about all that's ever buffered
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+// walks through bytecode as it's read from the source, reading numbers the way the version says to
+struct Reader<R: Read> {
+    source: R,
+    buffer: Vec<u8>, // read from the source but not consumed yet, from position on
+    position: usize,
+    offset: usize, // how much has been consumed, so where the next byte is in the whole bytecode
+    end: Option<usize>, // where the section being read ends, nothing past it can be read
+    checksum: Option<Crc32>, // of everything consumed since it was set
+    version: u8,
+}
+
+impl<R: Read> Reader<R> {
+    fn new(source: R) -> Reader<R> {
+        Reader { source, buffer: Vec::new(), position: 0, offset: 0, end: None, checksum: None, version: BYTECODE_VERSION_1 }
+    }
+
+    // makes sure there are at least wanted bytes buffered, unless the source runs out first
+    fn fill(&mut self, wanted: usize) -> Result<(), BytecodeError> {
+        while self.buffer.len() - self.position < wanted {
+            // move what's left to the front so the buffer doesn't keep growing
+            self.buffer.drain(..self.position);
+            self.position = 0;
+
+            let start = self.buffer.len();
+            self.buffer.resize(start + READ_CHUNK_SIZE, 0);
+
+            let read = self.source.read(&mut self.buffer[start..]);
+            self.buffer.truncate(start + *read.as_ref().unwrap_or(&0));
+
+            match read {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(BytecodeError::Io { offset: self.offset, message: err.to_string() }),
+            }
+        }
+
+        Ok(())
+    }
+
+    // how much of the section is left, or as much as could be wanted outside of one
+    fn left_in_section(&self) -> usize {
+        match self.end {
+            Some(end) => end.saturating_sub(self.offset),
+            None => usize::MAX,
+        }
+    }
+
+    // up to size of the next bytes without consuming them, fewer if the section or the bytecode ends first
+    fn peek(&mut self, size: usize) -> Result<&[u8], BytecodeError> {
+        let size = size.min(self.left_in_section());
+        self.fill(size)?;
+
+        let available = (self.buffer.len() - self.position).min(size);
+        Ok(&self.buffer[self.position..self.position + available])
+    }
+
+    fn at_end(&mut self) -> Result<bool, BytecodeError> {
+        Ok(self.peek(1)?.is_empty())
+    }
+
+    // moves past size bytes that have already been peeked at
+    fn consume(&mut self, size: usize) {
+        if let Some(checksum) = self.checksum.as_mut() {
+            checksum.update(&self.buffer[self.position..self.position + size]);
+        }
+
+        self.position += size;
+        self.offset += size;
+    }
+
+    // moves past the signature if it's next, giving back whether it was
+    fn skip_signature(&mut self, signature: &str) -> Result<bool, BytecodeError> {
+        if self.peek(signature.len())? == signature.as_bytes() {
+            self.consume(signature.len());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], BytecodeError> {
+        let offset = self.offset;
+        let bytes: [u8; N] = self.peek(N)?.try_into().map_err(|_| BytecodeError::UnexpectedEnd { offset })?;
+        self.consume(N);
+        Ok(bytes)
+    }
+
+    // a chunk at a time, so a bogus length can't make us allocate any more than the bytecode actually has
+    fn take_vec(&mut self, size: usize) -> Result<Vec<u8>, BytecodeError> {
+        let offset = self.offset;
+        let mut bytes = Vec::new();
+
+        while bytes.len() < size {
+            let chunk = self.peek((size - bytes.len()).min(READ_CHUNK_SIZE))?;
+
+            if chunk.is_empty() {
+                return Err(BytecodeError::UnexpectedEnd { offset });
+            }
+
+            let chunk_size = chunk.len();
+            bytes.extend_from_slice(chunk);
+            self.consume(chunk_size);
+        }
+
+        Ok(bytes)
+    }
+
+    // consumes whatever's left of the section
+    fn skip_to_end(&mut self) -> Result<(), BytecodeError> {
+        let offset = self.offset;
+
+        while self.left_in_section() > 0 {
+            let chunk_size = self.peek(READ_CHUNK_SIZE)?.len();
+
+            if chunk_size == 0 {
+                return Err(BytecodeError::UnexpectedEnd { offset });
+            }
+
+            self.consume(chunk_size);
+        }
+
+        Ok(())
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn f64(&mut self) -> Result<OperandSize, BytecodeError> {
+        Ok(OperandSize::from_le_bytes(self.take()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, BytecodeError> {
+        Ok(i64::from_le_bytes(self.take()?))
+    }
+
+    // a count, length or address, a plain u32 in version 1 and a varint after that
+    fn uint(&mut self) -> Result<u32, BytecodeError> {
+        if self.version == BYTECODE_VERSION_1 {
+            return self.u32();
+        }
+
+        // 7 bits at a time, lowest first, with the top bit set while there's more to come
+        let offset = self.offset;
+        let mut n: u32 = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.u8()?;
+            let bits = (byte & 0x7f) as u32;
+
+            // anything that doesn't fit in a u32 can't have come from us
+            if shift > 28 || (shift == 28 && bits > 0xf) {
+                return Err(BytecodeError::InvalidVarint { offset });
+            }
+            n |= bits << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+
+            shift += 7;
+        }
+    }
+
+    fn operand(&mut self, kind: OperandKind) -> Result<OperandSize, BytecodeError> {
+        match (self.version, kind) {
+            (BYTECODE_VERSION_1, _) | (_, OperandKind::Immediate) => self.f64(),
+            _ => Ok(self.uint()? as OperandSize),
+        }
+    }
+
+    // strings are a length followed by that many utf8 bytes
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let offset = self.offset;
+        let string_length = self.uint()? as usize;
+        let string_bytes = self.take_vec(string_length)?;
+
+        String::from_utf8(string_bytes).map_err(|_| BytecodeError::InvalidString { offset })
+    }
+}
+
+// the other way round to Reader
+struct Writer {
+    bytes: Vec<u8>,
+    version: u8,
+}
+
+impl Writer {
+    fn signature(&mut self, signature: &str) {
+        self.bytes.extend_from_slice(signature.as_bytes());
+    }
+
+    fn u8(&mut self, n: u8) {
+        self.bytes.push(n);
+    }
+
+    fn f64(&mut self, n: OperandSize) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn i64(&mut self, n: i64) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn uint(&mut self, mut n: u32) {
+        if self.version == BYTECODE_VERSION_1 {
+            self.bytes.extend_from_slice(&n.to_le_bytes());
+            return;
+        }
+
+        while n >= 0x80 {
+            self.bytes.push((n as u8 & 0x7f) | 0x80);
+            n >>= 7;
+        }
+        self.bytes.push(n as u8);
+    }
+
+    // false if it's an address or const key this version can't hold, which is one that isn't a whole u32 from version 2
+    fn operand(&mut self, kind: OperandKind, operand: OperandSize) -> bool {
+        match (self.version, kind) {
+            (BYTECODE_VERSION_1, _) | (_, OperandKind::Immediate) => self.f64(operand),
+            _ => match to_uint(operand) {
+                Some(n) => self.uint(n),
+                None => return false,
+            },
+        }
+
+        true
+    }
+
+    fn string(&mut self, string: &str) {
+        self.uint(string.len() as u32);
+        self.bytes.extend_from_slice(string.as_bytes());
+    }
+}
+
+// an address or const key as a u32, as long as it's whole and in range
+// `as u32` would turn -1 into 0 and 2.5 into 2, which is a different instruction entirely
+pub(crate) fn to_uint(operand: OperandSize) -> Option<u32> {
+    if operand < 0.0 || operand.fract() != 0.0 || operand > u32::MAX as OperandSize {
+        return None;
+    }
+
+    Some(operand as u32)
+}
+
+// crc32 as used by zip and png, so any other tool can check it too
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+// a crc32 worked out a piece at a time
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32(!0)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = CRC32_TABLE[((self.0 ^ *byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 { 0xedb88320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsm::assembler::assemble;
+    use crate::lsm::instruction::DEFAULT_INSTRUCTION_SET;
+
+    // every kind of const, with labels, symbols and debug info to go with them
+    const SOURCE: &str = "
+.const number -1.5
+.const int -3i
+.const big 123456789012345678901234567890i
+.const text \"hi\"
+.const yes true
+.const nothing nil
+.const list [1i \"two\" [nil]]
+.const bytes 0x00ff
+.export start
+.import elsewhere
+start:
+    PUSHC number
+    PUSHC int
+    PUSHC big
+    PUSHC text
+    PUSHC yes
+    PUSHC nothing
+    PUSHC list
+    PUSHC bytes
+    PUSH 2.5
+    BRZ start
+    BRA elsewhere
+    HLT
+";
+
+    fn assembled() -> Object {
+        assemble(SOURCE, "test.lsma", DEFAULT_INSTRUCTION_SET).unwrap()
+    }
+
+    // Object has no PartialEq since Value's is EQ, but the debug form covers every field
+    fn same(a: &Object, b: &Object) {
+        assert_eq!(format!("{:?}", a), format!("{:?}", b));
+    }
+
+    // PUSH 1, HLT
+    fn program() -> Object {
+        let code = vec![
+            RawInstruction { opcode: 1, operand: Some(1.0) },
+            RawInstruction { opcode: 0, operand: None },
+        ];
+
+        Object { code, ..Object::default() }
+    }
+
+    fn decode(bytecode: &[u8]) -> Result<Object, BytecodeError> {
+        Object::decode(bytecode, DEFAULT_INSTRUCTION_SET)
+    }
+
+    // a version 3 header and table for the sections, without checksums
+    fn version_3(sections: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut bytecode = BYTECODE_SIGNATURE.as_bytes().to_vec();
+        bytecode.extend_from_slice(&[BYTECODE_VERSION_3, 0, sections.len() as u8]);
+
+        for (kind, bytes) in sections {
+            bytecode.push(*kind);
+            bytecode.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        }
+
+        for (_, bytes) in sections {
+            bytecode.extend_from_slice(bytes);
+        }
+
+        bytecode
+    }
+
+    #[test]
+    fn a_corrupted_section_is_a_checksum_mismatch() {
+        let mut bytecode = program().encode(DEFAULT_INSTRUCTION_SET).unwrap();
+        let code_offset = bytecode.len() - 10; // PUSH, its f64 and HLT
+        *bytecode.last_mut().unwrap() = 0xff;
+
+        let error = decode(&bytecode).unwrap_err();
+        assert!(matches!(error, BytecodeError::ChecksumMismatch { kind: SECTION_CODE, offset, expected, found } if offset == code_offset && expected != found), "{:?}", error);
+    }
+
+    #[test]
+    fn a_truncated_section_is_an_unexpected_end() {
+        let bytecode = program().encode(DEFAULT_INSTRUCTION_SET).unwrap();
+
+        for length in [bytecode.len() - 1, bytecode.len() - 5] {
+            let error = decode(&bytecode[..length]).unwrap_err();
+            assert!(matches!(error, BytecodeError::UnexpectedEnd { offset } if offset <= length), "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn a_section_longer_than_its_contents_is_a_length_mismatch() {
+        // a nil, then a byte that isn't a const
+        let bytecode = version_3(&[(SECTION_CONSTS, &[CONST_NIL, 0xaa])]);
+
+        let error = decode(&bytecode).unwrap_err();
+        assert!(matches!(error, BytecodeError::SectionLengthMismatch { kind: SECTION_CONSTS, offset } if offset == bytecode.len() - 1), "{:?}", error);
+    }
+
+    #[test]
+    fn an_unknown_section_kind_is_skipped() {
+        let bytecode = version_3(&[(9, &[1, 2, 3]), (SECTION_CODE, &[0])]);
+
+        let object = decode(&bytecode).unwrap();
+        assert_eq!(object.code.len(), 1);
+
+        let inspection = Object::inspect(&bytecode, DEFAULT_INSTRUCTION_SET);
+        assert!(inspection.error.is_none());
+        assert_eq!(section_name(inspection.sections[0].kind), "unknown");
+    }
+
+    #[test]
+    fn an_unknown_signature_is_unrecognized() {
+        let mut bytecode = BYTECODE_SIGNATURE.as_bytes().to_vec();
+        bytecode.push(BYTECODE_VERSION_2);
+        bytecode.extend_from_slice(b"!WHAT");
+
+        let error = decode(&bytecode).unwrap_err();
+        assert!(matches!(error, BytecodeError::Unrecognized { offset: 6 }), "{:?}", error);
+    }
+
+    #[test]
+    fn a_varint_past_u32_is_invalid() {
+        // BRA to an address one bit bigger than a u32 holds
+        let mut bytecode = BYTECODE_SIGNATURE.as_bytes().to_vec();
+        bytecode.push(BYTECODE_VERSION_2);
+        bytecode.extend_from_slice(BYTECODE_INSTRUCTIONS_SIGNATURE.as_bytes());
+        bytecode.extend_from_slice(&[10, 0xff, 0xff, 0xff, 0xff, 0x1f]);
+
+        let error = decode(&bytecode).unwrap_err();
+        assert!(matches!(error, BytecodeError::InvalidVarint { offset: 13 }), "{:?}", error);
+
+        // and a section count that never ends
+        let mut bytecode = BYTECODE_SIGNATURE.as_bytes().to_vec();
+        bytecode.extend_from_slice(&[BYTECODE_VERSION_3, 0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]);
+
+        let error = decode(&bytecode).unwrap_err();
+        assert!(matches!(error, BytecodeError::InvalidVarint { offset: 7 }), "{:?}", error);
+    }
+
+    #[test]
+    fn an_unknown_version_is_unsupported() {
+        let mut bytecode = BYTECODE_SIGNATURE.as_bytes().to_vec();
+        bytecode.push(9);
+
+        assert!(matches!(decode(&bytecode), Err(BytecodeError::UnsupportedVersion(9))));
+        assert!(matches!(decode(b"!NOPE!"), Err(BytecodeError::InvalidSignature)));
+    }
+
+    #[test]
+    fn versions_1_and_2_round_trip() {
+        let object = assembled();
+        assert!(object.debug.is_some() && !object.exports.is_empty() && !object.imports.is_empty());
+
+        for version in [BYTECODE_VERSION_1, BYTECODE_VERSION_2] {
+            let bytecode = object.encode_version(version, DEFAULT_INSTRUCTION_SET).unwrap();
+            same(&decode(&bytecode).unwrap(), &object);
+        }
+    }
+
+    #[test]
+    fn only_whole_u32s_are_addresses_or_const_keys() {
+        assert_eq!(to_uint(0.0), Some(0));
+        assert_eq!(to_uint(u32::MAX as OperandSize), Some(u32::MAX));
+
+        for operand in [-1.0, 2.5, u32::MAX as OperandSize + 1.0, OperandSize::NAN, OperandSize::INFINITY] {
+            assert_eq!(to_uint(operand), None, "{}", operand);
+        }
+    }
+
+    #[test]
+    fn an_operand_that_isnt_a_whole_u32_cant_be_encoded() {
+        // BRA then PUSHC, both after an immediate that can be anything
+        for opcode in [10, 12] {
+            for operand in [-1.0, 2.5, u32::MAX as OperandSize + 1.0] {
+                let code = vec![
+                    RawInstruction { opcode: 1, operand: Some(-0.5) },
+                    RawInstruction { opcode, operand: Some(operand) },
+                ];
+                let object = Object { code, ..Object::default() };
+
+                for version in [BYTECODE_VERSION_2, BYTECODE_VERSION_3] {
+                    let result = object.encode_version(version, DEFAULT_INSTRUCTION_SET);
+                    assert!(matches!(result, Err(BytecodeError::InvalidOperand { address: 1, operand: found }) if found == operand), "{:?}", result);
+                }
+
+                // version 1 keeps every operand as an f64, so it can hold them
+                let bytecode = object.encode_version(BYTECODE_VERSION_1, DEFAULT_INSTRUCTION_SET).unwrap();
+                same(&decode(&bytecode).unwrap(), &object);
+            }
+        }
+    }
+
+    #[test]
+    fn version_3_round_trips_with_custom_sections() {
+        let mut object = assembled();
+        object.custom_sections.push(CustomSection { name: "notes".to_string(), data: vec![0, 1, 2, 255] });
+
+        let bytecode = object.encode_version(BYTECODE_VERSION_3, DEFAULT_INSTRUCTION_SET).unwrap();
+        same(&decode(&bytecode).unwrap(), &object);
+
+        // it has imports, so it's flagged as needing linking
+        let inspection = Object::inspect(&bytecode, DEFAULT_INSTRUCTION_SET);
+        assert_eq!(inspection.flags, Some(FLAG_CHECKSUMS | FLAG_NEEDS_LINKING));
+        assert!(inspection.sections.iter().all(|section| matches!(section.checksum, Checksum::Valid(_))));
+    }
+
+    #[test]
+    fn converting_between_versions_keeps_everything() {
+        let object = assembled();
+        let versions = [BYTECODE_VERSION_1, BYTECODE_VERSION_2, BYTECODE_VERSION_3];
+
+        // the same as lsm convert, decode whatever it is and encode it as another version
+        for from in versions {
+            let original = object.encode_version(from, DEFAULT_INSTRUCTION_SET).unwrap();
+
+            for to in versions {
+                let converted = decode(&original).unwrap().encode_version(to, DEFAULT_INSTRUCTION_SET).unwrap();
+                same(&decode(&converted).unwrap(), &object);
+
+                // and converting back gives exactly what we started with
+                let back = decode(&converted).unwrap().encode_version(from, DEFAULT_INSTRUCTION_SET).unwrap();
+                assert_eq!(back, original, "{} to {} and back", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn only_version_3_keeps_custom_sections() {
+        let mut object = program();
+        object.custom_sections.push(CustomSection { name: "notes".to_string(), data: vec![1] });
+
+        for version in [BYTECODE_VERSION_1, BYTECODE_VERSION_2] {
+            let bytecode = object.encode_version(version, DEFAULT_INSTRUCTION_SET).unwrap();
+            assert!(decode(&bytecode).unwrap().custom_sections.is_empty());
+        }
+
+        let result = object.encode_version(4, DEFAULT_INSTRUCTION_SET);
+        assert!(matches!(result, Err(BytecodeError::UnsupportedVersion(4))));
+    }
+}
@@ -0,0 +1,86 @@
+// a buffer of raw bytes, for binary data a string can't hold since strings have to be utf8
+// shared between everything holding it like a list is, and every index and read is checked
+use std::fmt;
+use crate::lsm::error::VMError;
+use crate::lsm::shared::Lock;
+
+pub struct Bytes {
+    bytes: Lock<Vec<u8>>,
+}
+
+impl Bytes {
+    pub fn new(bytes: Vec<u8>) -> Bytes {
+        Bytes { bytes: Lock::new(bytes) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.borrow().is_empty()
+    }
+
+    pub fn get(&self, index: i64) -> Result<u8, VMError> {
+        let bytes = self.bytes.borrow();
+        match usize::try_from(index) {
+            Ok(i) if i < bytes.len() => Ok(bytes[i]),
+            _ => Err(VMError::IndexOutOfBounds { index, len: bytes.len() }),
+        }
+    }
+
+    pub fn set(&self, index: i64, byte: u8) -> Result<(), VMError> {
+        let mut bytes = self.bytes.borrow_mut();
+        let len = bytes.len();
+        match usize::try_from(index) {
+            Ok(i) if i < len => {
+                bytes[i] = byte;
+                Ok(())
+            }
+            _ => Err(VMError::IndexOutOfBounds { index, len }),
+        }
+    }
+
+    // the bytes from start up to but not including end, as a new buffer
+    pub fn slice(&self, start: i64, end: i64) -> Result<Bytes, VMError> {
+        self.read(start, end).map(Bytes::new)
+    }
+
+    // a copy of size bytes from offset, for the instructions that read ints and floats
+    pub fn read_at(&self, offset: i64, size: usize) -> Result<Vec<u8>, VMError> {
+        self.read(offset, offset.saturating_add(size as i64))
+    }
+
+    // a copy of the bytes, so nothing's held locked while they're used
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.bytes.borrow().clone()
+    }
+
+    fn read(&self, start: i64, end: i64) -> Result<Vec<u8>, VMError> {
+        let bytes = self.bytes.borrow();
+
+        match (usize::try_from(start), usize::try_from(end)) {
+            (Ok(s), Ok(e)) if s <= e && e <= bytes.len() => Ok(bytes[s..e].to_vec()),
+            _ => Err(VMError::InvalidSlice { start, end, len: bytes.len() }),
+        }
+    }
+}
+
+// 0x and then two hex digits a byte, the same as a bytes literal in assembly
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x")?;
+
+        for byte in self.bytes.borrow().iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,138 @@
+use crate::lsm::error::VMError;
+use crate::lsm::shared::Shared;
+use crate::lsm::vm::{OperandSize, Value};
+
+// a handle's generation sits above the slot in the operand, and an operand is an f64 so only has 53 bits to play with
+const SLOT_BITS: u32 = 32;
+const MAX_GENERATION: u64 = (1 << (53 - SLOT_BITS)) - 1;
+
+// where a value lives in the const pool, and which value it was when the handle was given out
+// loaded consts are just their index, which never goes stale since they can't be deleted
+// stored values also carry the generation of their slot, so a handle to a deleted value can't reach whatever reused the slot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConstHandle {
+    Loaded(usize),
+    Stored { slot: usize, generation: u64 },
+}
+
+impl ConstHandle {
+    // how the handle is written as an operand or pushed onto the stack
+    // stored values always have a generation of at least 1, so they can never look like a loaded index
+    pub fn to_operand(self) -> OperandSize {
+        match self {
+            ConstHandle::Loaded(index) => index as OperandSize,
+            ConstHandle::Stored { slot, generation } => ((generation << SLOT_BITS) | slot as u64) as OperandSize,
+        }
+    }
+
+    pub fn from_operand(operand: OperandSize) -> Option<ConstHandle> {
+        if operand < 0.0 || operand.fract() != 0.0 || operand > (1u64 << 53) as OperandSize {
+            return None;
+        }
+
+        let bits = operand as u64;
+        let slot = (bits & ((1 << SLOT_BITS) - 1)) as usize;
+
+        Some(match bits >> SLOT_BITS {
+            0 => ConstHandle::Loaded(slot),
+            generation => ConstHandle::Stored { slot, generation },
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Slot {
+    generation: u64, // bumped every time the slot's value is deleted
+    value: Option<Value>,
+}
+
+// the program's consts, which are read-only and shared with every other run of it,
+// and the values stored while running, in slots that get reused once they're deleted
+#[derive(Clone, Debug, Default)]
+pub struct ConstPool {
+    loaded: Shared<Vec<Value>>,
+    slots: Vec<Slot>,
+    free: Vec<usize>, // empty slots, most recently freed last
+}
+
+impl ConstPool {
+    pub fn new(loaded: Shared<Vec<Value>>) -> ConstPool {
+        ConstPool { loaded, slots: Vec::new(), free: Vec::new() }
+    }
+
+    // swaps the read-only consts for another program's, leaving stored values alone
+    pub fn set_loaded(&mut self, loaded: Shared<Vec<Value>>) {
+        self.loaded = loaded;
+    }
+
+    pub fn loaded(&self) -> &[Value] {
+        &self.loaded
+    }
+
+    // how many values are stored right now, not counting the loaded consts
+    pub fn stored_len(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.value.is_some()).count()
+    }
+
+    pub fn get(&self, handle: ConstHandle) -> Result<&Value, VMError> {
+        match handle {
+            ConstHandle::Loaded(index) => self.loaded.get(index).ok_or(VMError::MissingConst(handle.to_operand())),
+            ConstHandle::Stored { slot, generation } => {
+                let slot = self.slot(handle, slot, generation)?;
+                slot.value.as_ref().ok_or(VMError::StaleConst(handle.to_operand()))
+            }
+        }
+    }
+
+    // stores the value in the most recently freed slot, or a new one if none are free
+    pub fn store(&mut self, value: Value) -> ConstHandle {
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(Slot { generation: 1, value: None });
+                self.slots.len() - 1
+            }
+        };
+
+        self.slots[slot].value = Some(value);
+        ConstHandle::Stored { slot, generation: self.slots[slot].generation }
+    }
+
+    // deletes a stored value and hands it back, any other handle to it is stale from now on
+    pub fn remove(&mut self, handle: ConstHandle) -> Result<Value, VMError> {
+        let (index, generation) = match handle {
+            ConstHandle::Loaded(index) if index < self.loaded.len() => return Err(VMError::ReadOnlyConst(handle.to_operand())),
+            ConstHandle::Loaded(_) => return Err(VMError::MissingConst(handle.to_operand())),
+            ConstHandle::Stored { slot, generation } => (slot, generation),
+        };
+
+        self.slot(handle, index, generation)?;
+
+        let slot = &mut self.slots[index];
+        let value = slot.value.take().ok_or(VMError::StaleConst(handle.to_operand()))?;
+        slot.generation += 1;
+
+        // a slot that's run out of generations would hand out handles that look like old ones, so it's retired instead
+        if slot.generation <= MAX_GENERATION {
+            self.free.push(index);
+        }
+
+        Ok(value)
+    }
+
+    // the slot a stored handle points at, as long as the handle isn't from before the slot was reused
+    fn slot(&self, handle: ConstHandle, slot: usize, generation: u64) -> Result<&Slot, VMError> {
+        let found = self.slots.get(slot).ok_or(VMError::MissingConst(handle.to_operand()))?;
+
+        // a newer generation than the slot's never came from us
+        if generation > found.generation {
+            return Err(VMError::MissingConst(handle.to_operand()));
+        }
+
+        if generation < found.generation {
+            return Err(VMError::StaleConst(handle.to_operand()));
+        }
+
+        Ok(found)
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+use crate::lsm::stack::Stack;
+use crate::lsm::vm::{Handler, OperandSize, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoroutineStatus {
+    Dead, // finished, either by HLT or an uncaught error
+    Suspended, // created or yielded, waiting to be resumed
+    Running, // currently executing
+    Normal, // resumed another coroutine and is waiting on it
+}
+
+impl CoroutineStatus {
+    // what COSTATUS pushes, dead is 0 so a loop can BRZ out once it's done
+    pub fn code(&self) -> OperandSize {
+        match self {
+            CoroutineStatus::Dead => 0.0,
+            CoroutineStatus::Suspended => 1.0,
+            CoroutineStatus::Running => 2.0,
+            CoroutineStatus::Normal => 3.0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoroutineStatus::Dead => "dead",
+            CoroutineStatus::Suspended => "suspended",
+            CoroutineStatus::Running => "running",
+            CoroutineStatus::Normal => "normal",
+        }
+    }
+}
+
+// everything a coroutine needs to pick up where it left off
+// while it's running these are swapped into the vm, so here they hold the resumer's state instead
+pub struct Coroutine {
+    pub stack: Stack<Value>,
+    pub handlers: Vec<Handler>,
+    pub pc: usize,
+    pub status: CoroutineStatus,
+}
+
+impl Coroutine {
+    pub fn new(address: usize, stack_size: usize) -> Coroutine {
+        Coroutine { stack: Stack::new(stack_size), handlers: Vec::new(), pc: address, status: CoroutineStatus::Suspended }
+    }
+}
+
+// a coroutine can end up on its own stack, so don't print the stack
+impl fmt::Debug for Coroutine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Coroutine")
+            .field("pc", &self.pc)
+            .field("status", &self.status)
+            .finish()
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+// where a run of instructions came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceRange {
+    pub start: usize, // first address covered
+    pub end: usize, // one past the last address covered
+    pub file: usize, // index into DebugInfo::files
+    pub line: u32,
+    pub column: u32,
+}
+
+// a named address, e.g. a label
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DebugSymbol {
+    pub name: String,
+    pub address: usize,
+}
+
+// a resolved position in a source file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceLocation<'a> {
+    pub file: &'a str,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for SourceLocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+// maps addresses back to source, only there if whatever produced the bytecode emitted it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub ranges: Vec<SourceRange>, // sorted by start address and never overlapping
+    pub symbols: Vec<DebugSymbol>, // sorted by address
+}
+
+impl DebugInfo {
+    // the source position the instruction at this address came from
+    pub fn location(&self, address: usize) -> Option<SourceLocation<'_>> {
+        // find the last range starting at or before the address
+        let index = self.ranges.partition_point(|range| range.start <= address);
+        let range = self.ranges.get(index.checked_sub(1)?)?;
+
+        if address >= range.end {
+            return None;
+        }
+
+        Some(SourceLocation { file: self.files.get(range.file)?, line: range.line, column: range.column })
+    }
+
+    // every symbol naming exactly this address
+    pub fn symbols_at(&self, address: usize) -> impl Iterator<Item = &str> {
+        let start = self.symbols.partition_point(|symbol| symbol.address < address);
+
+        self.symbols[start..].iter()
+            .take_while(move |symbol| symbol.address == address)
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    // the closest symbol at or before the address and how far past it the address is, e.g. loop+2
+    pub fn nearest_symbol(&self, address: usize) -> Option<(&str, usize)> {
+        let index = self.symbols.partition_point(|symbol| symbol.address <= address);
+        let symbol = self.symbols.get(index.checked_sub(1)?)?;
+
+        Some((symbol.name.as_str(), address - symbol.address))
+    }
+
+    // e.g. "12 (main.lsma:4:5, in loop+2)", or just "12" if we know nothing about it
+    pub fn describe(&self, address: usize) -> String {
+        let mut details = Vec::new();
+
+        if let Some(location) = self.location(address) {
+            details.push(location.to_string());
+        }
+
+        match self.nearest_symbol(address) {
+            Some((name, 0)) => details.push(format!("in {}", name)),
+            Some((name, offset)) => details.push(format!("in {}+{}", name, offset)),
+            None => {}
+        }
+
+        if details.is_empty() {
+            address.to_string()
+        } else {
+            format!("{} ({})", address, details.join(", "))
+        }
+    }
+
+    // appends another program's debug info, whose code starts at code_offset
+    pub fn append(&mut self, other: &DebugInfo, code_offset: usize) {
+        let file_offset = self.files.len();
+        self.files.extend(other.files.iter().cloned());
+
+        self.ranges.extend(other.ranges.iter().map(|range| SourceRange {
+            start: range.start + code_offset,
+            end: range.end + code_offset,
+            file: range.file + file_offset,
+            line: range.line,
+            column: range.column,
+        }));
+        self.ranges.sort_by_key(|range| range.start);
+
+        self.symbols.extend(other.symbols.iter().map(|symbol| DebugSymbol { name: symbol.name.clone(), address: symbol.address + code_offset }));
+        self.symbols.sort_by_key(|symbol| symbol.address);
+    }
+}
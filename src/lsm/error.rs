@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::fmt;
+use crate::lsm::coroutine::CoroutineStatus;
+use crate::lsm::instruction::OpcodeSize;
+use crate::lsm::intern::intern_string;
+use crate::lsm::vm::{OperandSize, Value};
+
+// everything that can go wrong while the vm is running
+// all of these can be caught by a TRY handler inside the program
+#[derive(Clone, Debug)]
+pub enum VMError {
+    StackUnderflow,
+    StackOverflow,
+    TypeMismatch { expected: &'static str, found: &'static str },
+    MissingOperand,
+    MissingConst(OperandSize),
+    DivisionByZero, // integer DIV or MOD by 0, numbers give inf or nan instead
+    IntegerOverflow(&'static str), // the instruction whose result didn't fit in an int
+    InvalidInteger(OperandSize), // a number that can't be turned into an int, e.g. 1.5 or nan
+    InvalidAddress(OperandSize), // a branch or handler address that's negative or not a whole number
+    InvalidShift(i64), // shifting by a negative amount
+    Unordered, // CMP with a nan, which is neither less, equal nor greater
+    InvalidRational(OperandSize), // TORAT on nan or an infinity
+    IndexOutOfBounds { index: i64, len: usize },
+    InvalidSlice { start: i64, end: i64, len: usize }, // a slice that goes outside the list or ends before it starts
+    EmptyList, // LPOP with nothing to pop
+    InvalidKey(&'static str), // a map key that isn't a number, int, string, bool or nil, or is nan
+    MissingKey(Value), // MGET on a key that isn't in the map
+    InvalidByte(i64), // BSET with something that doesn't fit in a byte
+    InvalidUtf8 { offset: usize }, // BTOSTR on bytes that aren't a string, offset is where the first bad byte is
+    InvalidWidth(OperandSize), // reading an int or float of a size there isn't one of
+    OutOfMemory(usize), // BALLOC of more bytes than can be allocated
+    StaleConst(OperandSize), // a handle to a stored value that's since been deleted
+    ReadOnlyConst(OperandSize), // DELETEC on a const that was loaded with the program
+    IllegalInstruction(OpcodeSize),
+    NoHandler, // ENDTRY without a matching TRY
+    NotInCoroutine, // YIELD outside of a coroutine
+    CannotResume(CoroutineStatus), // RESUME on a coroutine that isn't suspended
+    #[cfg(feature = "sync")]
+    NoActorRuntime, // SEND/RECV outside of an ActorRuntime
+    #[cfg(feature = "sync")]
+    MailboxClosed(String), // RECV when nothing could ever be sent to the mailbox again
+    Thrown(Value), // THROW with nothing to catch it
+}
+
+impl VMError {
+    // the value a handler receives on the stack when it catches this error
+    pub fn into_value(self) -> Value {
+        match self {
+            VMError::Thrown(value) => value,
+            error => Value::Str(intern_string(error.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VMError::StackUnderflow => write!(f, "stack underflow"),
+            VMError::StackOverflow => write!(f, "stack overflow"),
+            VMError::TypeMismatch { expected, found } => write!(f, "type mismatch: expected {}, found {}", expected, found),
+            VMError::MissingOperand => write!(f, "instruction is missing its operand"),
+            VMError::MissingConst(key) => write!(f, "no value at const key {}", key),
+            VMError::DivisionByZero => write!(f, "integer division by zero"),
+            VMError::IntegerOverflow(instruction) => write!(f, "integer overflow in {}", instruction),
+            VMError::InvalidInteger(n) => write!(f, "{} isn't a whole number that fits in an int", n),
+            VMError::InvalidAddress(address) => write!(f, "{} isn't a valid address", address),
+            VMError::InvalidShift(amount) => write!(f, "can't shift by {}", amount),
+            VMError::Unordered => write!(f, "nan can't be compared"),
+            VMError::InvalidRational(n) => write!(f, "{} can't be turned into a rational", n),
+            VMError::IndexOutOfBounds { index, len } => write!(f, "index {} is out of bounds for length {}", index, len),
+            VMError::InvalidSlice { start, end, len } => write!(f, "slice {}..{} is out of bounds for length {}", start, end, len),
+            VMError::EmptyList => write!(f, "can't pop from an empty list"),
+            VMError::InvalidKey(kind) => write!(f, "{} can't be a map key", kind),
+            VMError::MissingKey(key) => write!(f, "key {} isn't in the map", key),
+            VMError::InvalidByte(n) => write!(f, "{} doesn't fit in a byte", n),
+            VMError::InvalidUtf8 { offset } => write!(f, "bytes aren't valid utf8, from offset {}", offset),
+            VMError::InvalidWidth(width) => write!(f, "can't read {} bytes, ints are 1, 2, 4 or 8 and floats 4 or 8", width),
+            VMError::OutOfMemory(size) => write!(f, "couldn't allocate {} bytes", size),
+            VMError::StaleConst(key) => write!(f, "const handle {} is stale, its value has been deleted", key),
+            VMError::ReadOnlyConst(key) => write!(f, "const {} was loaded with the program and can't be deleted", key),
+            VMError::IllegalInstruction(opcode) => write!(f, "illegal instruction {}", opcode),
+            VMError::NoHandler => write!(f, "ENDTRY without a matching TRY"),
+            VMError::NotInCoroutine => write!(f, "YIELD outside of a coroutine"),
+            VMError::CannotResume(status) => write!(f, "cannot resume a {} coroutine", status.name()),
+            #[cfg(feature = "sync")]
+            VMError::NoActorRuntime => write!(f, "not running inside an actor runtime"),
+            #[cfg(feature = "sync")]
+            VMError::MailboxClosed(mailbox) => write!(f, "mailbox {} is closed", mailbox),
+            VMError::Thrown(value) => write!(f, "uncaught exception: {:?}", value),
+        }
+    }
+}
+
+impl Error for VMError {}
@@ -1,17 +1,43 @@
-use crate::lsm::vm::{OperandSize, ToNumber, Value, VM};
+use crate::lsm::arithmetic::{arithmetic, numeric_cmp, to_rational, ArithmeticOp, OverflowMode};
+use crate::lsm::bigint::BigInt;
+use crate::lsm::bytes::Bytes;
+use crate::lsm::error::VMError;
+use crate::lsm::intern::intern_string;
+use crate::lsm::iter::Iter;
+use crate::lsm::list::List;
+use crate::lsm::map::Map;
+use crate::lsm::rational::Rational;
+use crate::lsm::shared::Shared;
+use crate::lsm::vm::{number_to_int, OperandSize, ToNumber, Value, VM};
 
 pub type OpcodeSize = u8;
 
-pub type InstructionFunc = fn(&mut VM, Option<Value>);
+pub type InstructionFunc = fn(&mut VM, Option<Value>) -> Result<(), VMError>;
+
+// what an instruction's operand means, so tools like the linker know what needs relocating
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandKind {
+    None, // takes no operand
+    Immediate, // a plain number
+    Address, // a virtual address in the code
+    ConstKey, // a key into the const pool
+}
 
 #[derive(Clone)]
 pub struct Instruction {
     pub name:      &'static str,
     pub opcode: OpcodeSize,
-    pub requires_operand: bool,
+    pub operand: OperandKind,
     pub func: InstructionFunc,
 }
 
+impl Instruction {
+    pub fn requires_operand(&self) -> bool {
+        self.operand != OperandKind::None
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct RawInstruction {
     pub opcode: OpcodeSize,
     pub operand: Option<OperandSize>,
@@ -23,17 +49,78 @@ instruction set for reference
 NAME - OPCODE - DETAILS
 PUSH - 1 - expects number as operand and pushes it onto stack
 POP - 2 - pops a value off the stack
-ADD - 3 - adds the two topmost values on the stack
+ADD - 3 - adds the two topmost values on the stack (two ints give an int, anything with a number in it gives a number)
 MUL - 4 - multiplies the two topmost values on the stack
 SUB - 5 - subtracts second value on stack by first value on the stack
-DIV - 6 - divides second value on stack by first value on the stack
+DIV - 6 - divides second value on stack by first value on the stack (ints truncate, and dividing an int by 0 is an error)
 MOD - 7 - gets the modulus for second value on stack by first value on the stack
-BRZ - 8 - expects virtual address, and it branches to that if top value on stack is zero
+BRZ - 8 - expects virtual address, and it branches to that if top value on stack is zero (or false)
 BRP - 9 - expects virtual address, and it branches to that if top value on stack is zero or positive
 BRA - 10 - expects virtual address, and it branches to that
 DUP - 11 - duplicates the top of the stack
 OUT - 100 - prints the topmost item on the stack (debug)
-HLT - 0 - halts the program
+IN - 101 - suspends the vm until the host supplies a value, which gets pushed onto the stack
+HLT - 0 - halts the program (inside a coroutine it finishes the coroutine, handing back the top of its stack)
+TRY - 15 - expects virtual address of a handler, anything thrown before the matching ENDTRY unwinds the stack to its depth at TRY and branches to the handler with the error value on top
+ENDTRY - 16 - ends the innermost TRY block
+THROW - 17 - throws the top value on the stack as an exception
+LOADC - 18 - pops a const handle (e.g. from STOREC) and pushes the value it points at
+FREEC - 19 - pops a const handle and deletes the stored value it points at, any copies of the handle go stale
+CREATE_CO - 20 - expects virtual address, and pushes a new suspended coroutine starting there
+RESUME - 21 - pops a value then a coroutine, and switches to the coroutine with the value on top of its stack
+YIELD - 22 - pops a value, suspends the running coroutine and pushes the value onto the resumer's stack (outside a coroutine it suspends the vm, handing the value to the host)
+COSTATUS - 23 - pops a coroutine and pushes its status (0 dead, 1 suspended, 2 running, 3 normal)
+EQ - 26 - pops two values and pushes whether they're equal, strings are compared by pointer first since they're interned
+CONCAT - 27 - joins the second string on the stack with the first, pushing the result
+TOSTR - 28 - pops a value and pushes it as a string, in the same form OUT would write it (but strings aren't quoted)
+PUSHI - 29 - expects a whole number as operand and pushes it as an int
+TOINT - 30 - pops a number, bigint or rational and pushes it as an int, erroring if it isn't whole or doesn't fit
+TONUM - 31 - pops an int, bigint or rational and pushes it as a number, which can lose precision
+ADDW, SUBW, MULW - 32 to 34 - ADD, SUB and MUL that wrap on int overflow whatever the vm's overflow mode is
+ADDC, SUBC, MULC - 35 to 37 - ADD, SUB and MUL that raise an error on int overflow whatever the vm's overflow mode is
+ADDS, SUBS, MULS - 38 to 40 - ADD, SUB and MUL that saturate on int overflow whatever the vm's overflow mode is
+AND - 41 - bitwise and of the two topmost ints on the stack
+OR - 42 - bitwise or of the two topmost ints on the stack
+XOR - 43 - bitwise xor of the two topmost ints on the stack
+NOT - 44 - flips every bit of the int on top of the stack
+SHL - 45 - shifts the second int on the stack left by the first, anything from 64 up gives 0
+SHR - 46 - shifts the second int on the stack right by the first, filling with zeroes (logical)
+SAR - 47 - shifts the second int on the stack right by the first, filling with the sign bit (arithmetic)
+ROTL - 48 - rotates the second int on the stack left by the first, mod 64
+ROTR - 49 - rotates the second int on the stack right by the first, mod 64
+POPCNT - 50 - pops an int and pushes how many of its bits are set
+CMP - 51 - compares the second value on the stack with the first, pushing -1, 0 or 1 as an int (numbers of any kind with each other, or two strings)
+RAT - 52 - pops a denominator then a numerator, both ints or bigints, and pushes the fraction in lowest terms as a rational
+TORAT - 53 - pops a number, int or bigint and pushes exactly the same value as a rational (a number like 0.1 is only close to a tenth, so it's whatever it really holds)
+ITER - 54 - pops a string, list, bytes or map and pushes an iterator over its chars, values, bytes or keys (an iterator stays as it is)
+NEXT - 55 - expects virtual address, and pushes the next value from the iterator on top of the stack, leaving the iterator under it; once there are none left it pops the iterator and branches instead
+RANGE - 56 - pops an end then a start, and pushes an iterator over the ints from start up to end
+NEWLIST - 80 - expects a count as operand, pops that many values and pushes a list of them, the deepest first
+LPUSH - 81 - pops a value then a list, and adds the value to the end of the list
+LPOP - 82 - pops a list, removes its last value and pushes it
+LGET - 83 - pops an index then a list, and pushes the value at the index
+LSET - 84 - pops a value, an index then a list, and sets the value at the index
+LLEN - 85 - pops a list and pushes its length as an int
+LSLICE - 86 - pops an end index, a start index then a list, and pushes a new list of the values from start up to end
+LCONCAT - 87 - pops two lists and pushes a new list of the second's values followed by the first's
+NEWMAP - 88 - pushes a new empty map
+MGET - 89 - pops a key then a map, and pushes the key's value, erroring if it isn't there
+MSET - 90 - pops a value, a key then a map, and sets the key to the value
+MHAS - 91 - pops a key then a map, and pushes whether the key is there
+MDEL - 92 - pops a key then a map, and takes the key out of the map if it's there
+MKEYS - 93 - pops a map and pushes a list of its keys, in the order they were first set
+MLEN - 94 - pops a map and pushes how many keys it has as an int
+BALLOC - 110 - pops a length and pushes that many zero bytes
+BGET - 111 - pops an index then bytes, and pushes the byte at the index as an int
+BSET - 112 - pops a byte (an int from 0 to 255), an index then bytes, and sets the byte at the index
+BLEN - 113 - pops bytes and pushes how many there are as an int
+BSLICE - 114 - pops an end index, a start index then bytes, and pushes new bytes from start up to end
+BCONCAT - 115 - pops two lots of bytes and pushes new bytes of the second's followed by the first's
+BTOSTR - 116 - pops bytes and pushes them as a string, erroring if they aren't valid utf8
+STRTOB - 117 - pops a string and pushes its utf8 bytes
+BREADILE, BREADIBE - 118 to 119 - expect a size (1, 2, 4 or 8) as operand, pop an offset then bytes, and push the signed int that many bytes long at the offset, little or big endian
+BREADULE, BREADUBE - 120 to 121 - the same for unsigned ints, an 8 byte one too big for an int gives a bigint
+BREADFLE, BREADFBE - 122 to 123 - the same for floats, which are 4 or 8 bytes
 ...
  */
 
@@ -42,168 +129,1080 @@ pub const DEFAULT_INSTRUCTION_SET: &[Instruction] = &[
     Instruction {
         name: "PUSH",
         opcode: 1,
-        requires_operand: true,
+        operand: OperandKind::Immediate,
         func: |vm, operand| {
             // although it's a value, i mean we can push anything provided..
-            vm.push(operand.unwrap());
+            vm.push(operand.ok_or(VMError::MissingOperand)?)?;
+            Ok(())
         }
     },
     Instruction {
         name: "POP",
         opcode: 2,
-        requires_operand: false,
+        operand: OperandKind::None,
         func: |vm, _operand| {
-            vm.pop().unwrap();
+            vm.pop()?;
+            Ok(())
         }
     },
     Instruction {
         name: "ADD",
         opcode: 3,
-        requires_operand: false,
+        operand: OperandKind::None,
         func: |vm, _operand| {
-            let a = vm.pop().expect("stack underflow > 1");
-            let b = vm.pop().expect("stack underflow > 2");
+            let a = vm.pop()?;
+            let b = vm.pop()?;
 
-            vm.push(Value::Number(a.to_number().unwrap() + b.to_number().unwrap()));
+            vm.push(arithmetic(ArithmeticOp::Add, b, a, vm.overflow_mode())?)?;
+            Ok(())
         }
     },
     Instruction {
         name: "MUL",
         opcode: 4,
-        requires_operand: false,
+        operand: OperandKind::None,
         func: |vm, _operand| {
-            let a = vm.pop().expect("stack underflow > 1");
-            let b = vm.pop().expect("stack underflow > 2");
-            vm.push(Value::Number(a.to_number().unwrap() * b.to_number().unwrap()));
+            let a = vm.pop()?;
+            let b = vm.pop()?;
+            vm.push(arithmetic(ArithmeticOp::Mul, b, a, vm.overflow_mode())?)?;
+            Ok(())
         }
     },
     Instruction {
         name: "SUB",
         opcode: 5,
-        requires_operand: false,
+        operand: OperandKind::None,
         func: |vm, _operand| {
-            let a = vm.pop().expect("stack underflow > 1");
-            let b = vm.pop().expect("stack underflow > 2");
-            vm.push(Value::Number(b.to_number().unwrap() - a.to_number().unwrap()));
+            let a = vm.pop()?;
+            let b = vm.pop()?;
+            vm.push(arithmetic(ArithmeticOp::Sub, b, a, vm.overflow_mode())?)?;
+            Ok(())
         }
     },
     Instruction {
         name: "DIV",
         opcode: 6,
-        requires_operand: false,
+        operand: OperandKind::None,
         func: |vm, _operand| {
-            let a = vm.pop().expect("stack underflow > 1");
-            let b = vm.pop().expect("stack underflow > 2");
-            vm.push(Value::Number(b.to_number().unwrap() / a.to_number().unwrap()));
+            let a = vm.pop()?;
+            let b = vm.pop()?;
+            vm.push(arithmetic(ArithmeticOp::Div, b, a, vm.overflow_mode())?)?;
+            Ok(())
         }
     },
     Instruction {
         name: "MOD",
         opcode: 7,
-        requires_operand: false,
+        operand: OperandKind::None,
         func: |vm, _operand| {
-            let a = vm.pop().expect("stack underflow > 1");
-            let b = vm.pop().expect("stack underflow > 2");
-            vm.push(Value::Number(b.to_number().unwrap() % a.to_number().unwrap()));
+            let a = vm.pop()?;
+            let b = vm.pop()?;
+            vm.push(arithmetic(ArithmeticOp::Mod, b, a, vm.overflow_mode())?)?;
+            Ok(())
         }
     },
     Instruction {
         name: "BRZ",
         opcode: 8,
-        requires_operand: true,
+        operand: OperandKind::Address,
         func: |vm, operand| {
-            let a = vm.pop().expect("stack underflow > 1");
-            if a.to_number().expect("couldn't turn into number") == 0 as OperandSize {
-                vm.branch(operand.unwrap().to_number().unwrap());
+            let a = vm.pop()?;
+            let zero = match a {
+                Value::Bool(b) => !b,
+                a => a.to_number()? == 0 as OperandSize,
+            };
+
+            if zero {
+                vm.branch(operand.ok_or(VMError::MissingOperand)?.to_number()?)?;
             }
+            Ok(())
         }
     },
     Instruction {
         name: "BRP",
         opcode: 9,
-        requires_operand: true,
+        operand: OperandKind::Address,
         func: |vm, operand| {
-            let a = vm.pop().expect("stack underflow > 1");
-            if a.to_number().expect("couldn't turn into number") >= 0 as OperandSize {
-                vm.branch(operand.unwrap().to_number().unwrap());
+            let a = vm.pop()?;
+            if a.to_number()? >= 0 as OperandSize {
+                vm.branch(operand.ok_or(VMError::MissingOperand)?.to_number()?)?;
             }
+            Ok(())
         }
     },
     Instruction {
         name: "BRA",
         opcode: 10,
-        requires_operand: true,
+        operand: OperandKind::Address,
         func: |vm, operand| {
-            vm.branch(operand.unwrap().to_number().unwrap());
+            vm.branch(operand.ok_or(VMError::MissingOperand)?.to_number()?)?;
+            Ok(())
         }
     },
     Instruction {
         name: "HLT",
         opcode: 0,
-        requires_operand: false,
+        operand: OperandKind::None,
         func: |vm, _operand| {
-            vm.halt();
+            if vm.in_coroutine() {
+                vm.finish_coroutine()?;
+            } else {
+                vm.halt();
+            }
+            Ok(())
         }
     },
     Instruction {
         name: "OUT",
         opcode: 100,
-        requires_operand: false,
+        operand: OperandKind::None,
         func: |vm, _operand| {
-            let a = vm.pop().expect("stack underflow > 1");
+            let a = vm.pop()?;
             println!("{:?}", a);
             // and push back on stack for like a peek like behaviour
-            vm.push(a);
+            vm.push(a)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "IN",
+        opcode: 101,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            // the host pushes the value when it resumes us
+            vm.request_input();
+            Ok(())
         }
     },
     Instruction {
         name: "DUP",
         opcode: 11,
-        requires_operand: false,
+        operand: OperandKind::None,
         func: |vm, _operand| {
-            let a_ref = vm.peek().expect("stack underflow > 1");
+            let a_ref = vm.peek().ok_or(VMError::StackUnderflow)?;
             let a = a_ref.clone();
-            vm.push(a);
+            vm.push(a)?;
+            Ok(())
         }
     },
     Instruction {
         name: "PUSHC",
         opcode: 12,
-        requires_operand: true,
+        operand: OperandKind::ConstKey,
         func: |vm, operand| {
             // operand is the key for the const pool
             // turn operand into an integer
-            let a = vm.get_const_copy(operand.clone().unwrap().to_number().expect("operand not supplied/not a proper key"));
+            let key = operand.ok_or(VMError::MissingOperand)?.to_number()?;
+            let a = vm.get_const_copy(key)?;
 
-            match a {
-                Some(a) => {
-                    vm.push(a);
-                }
-                None => {
-                    panic!("no value at key provided {:?}", operand.unwrap());
-                }
-            }
+            vm.push(a)?;
+            Ok(())
         }
     },
     Instruction {
         name: "STOREC",
         opcode: 13,
-        requires_operand: true,
+        operand: OperandKind::Immediate,
         func: |vm, _operand| {
             // stores top of stack as a const
-            let a = vm.pop().expect("stack underflow > 1");
-            let key = vm.store_const(a);
+            let a = vm.pop()?;
+            let handle = vm.store_const(a);
 
-            vm.push(Value::Number(key as OperandSize));
+            vm.push(Value::Number(handle.to_operand()))?;
+            Ok(())
         }
     },
 
     Instruction {
         name: "DELETEC",
         opcode: 14,
-        requires_operand: true,
+        // only stored values can be deleted, and their handles go past what a const key can be encoded as
+        // so it's a plain number, which the linker leaves alone since it isn't one of the object's consts anyway
+        operand: OperandKind::Immediate,
+        func: |vm, operand| {
+            vm.remove_const(operand.ok_or(VMError::MissingOperand)?.to_number()?)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "LOADC",
+        opcode: 18,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            // the same as PUSHC, but for handles that only exist at runtime
+            let key = vm.pop()?.to_number()?;
+            let a = vm.get_const_copy(key)?;
+
+            vm.push(a)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "FREEC",
+        opcode: 19,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let key = vm.pop()?.to_number()?;
+            vm.remove_const(key)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "TRY",
+        opcode: 15,
+        operand: OperandKind::Address,
+        func: |vm, operand| {
+            // operand is the address of the handler
+            vm.push_handler(operand.ok_or(VMError::MissingOperand)?.to_number()?)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "ENDTRY",
+        opcode: 16,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            vm.pop_handler()?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "THROW",
+        opcode: 17,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            Err(VMError::Thrown(a))
+        }
+    },
+    Instruction {
+        name: "CREATE_CO",
+        opcode: 20,
+        operand: OperandKind::Address,
         func: |vm, operand| {
-            vm.remove_const(operand.unwrap().to_number().unwrap());
+            // operand is the address the coroutine starts at
+            let a = vm.create_coroutine(operand.ok_or(VMError::MissingOperand)?.to_number()?)?;
+            vm.push(a)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "RESUME",
+        opcode: 21,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            let b = vm.pop()?;
+            vm.resume_coroutine(b.to_coroutine()?, a)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "YIELD",
+        opcode: 22,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+
+            if vm.in_coroutine() {
+                vm.yield_coroutine(a)?;
+            } else {
+                vm.yield_to_host(a);
+            }
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "COSTATUS",
+        opcode: 23,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            let status = a.to_coroutine()?.borrow().status;
+            vm.push(Value::Number(status.code()))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "EQ",
+        opcode: 26,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            let b = vm.pop()?;
+
+            vm.push(Value::Bool(a == b))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "CONCAT",
+        opcode: 27,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_str()?;
+            let b = vm.pop()?.to_str()?;
+
+            vm.push(Value::Str(intern_string(format!("{}{}", b, a))))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "TOSTR",
+        opcode: 28,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            vm.push(Value::Str(a.to_display_string()))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "PUSHI",
+        opcode: 29,
+        operand: OperandKind::Immediate,
+        func: |vm, operand| {
+            // operands are numbers, so only ints up to 2^53 can be pushed this way, anything bigger wants a const
+            let n = operand.ok_or(VMError::MissingOperand)?.to_number()?;
+            vm.push(Value::Int(number_to_int(n)?))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "TOINT",
+        opcode: 30,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = match vm.pop()? {
+                Value::Int(n) => n,
+                // a bigint only ever holds something too big for an int
+                Value::BigInt(_) => return Err(VMError::IntegerOverflow("TOINT")),
+                Value::Rational(n) if n.is_whole() => match Value::from(n.numerator().clone()) {
+                    Value::Int(n) => n,
+                    _ => return Err(VMError::IntegerOverflow("TOINT")),
+                },
+                Value::Rational(n) => return Err(VMError::InvalidInteger(n.to_f64())),
+                a => number_to_int(a.to_number()?)?,
+            };
+
+            vm.push(Value::Int(a))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "TONUM",
+        opcode: 31,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_number()?;
+            vm.push(Value::Number(a))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "ADDW",
+        opcode: 32,
+        operand: OperandKind::None,
+        func: |vm, _operand| arithmetic_with_mode(vm, ArithmeticOp::Add, OverflowMode::Wrapping),
+    },
+    Instruction {
+        name: "SUBW",
+        opcode: 33,
+        operand: OperandKind::None,
+        func: |vm, _operand| arithmetic_with_mode(vm, ArithmeticOp::Sub, OverflowMode::Wrapping),
+    },
+    Instruction {
+        name: "MULW",
+        opcode: 34,
+        operand: OperandKind::None,
+        func: |vm, _operand| arithmetic_with_mode(vm, ArithmeticOp::Mul, OverflowMode::Wrapping),
+    },
+    Instruction {
+        name: "ADDC",
+        opcode: 35,
+        operand: OperandKind::None,
+        func: |vm, _operand| arithmetic_with_mode(vm, ArithmeticOp::Add, OverflowMode::Checked),
+    },
+    Instruction {
+        name: "SUBC",
+        opcode: 36,
+        operand: OperandKind::None,
+        func: |vm, _operand| arithmetic_with_mode(vm, ArithmeticOp::Sub, OverflowMode::Checked),
+    },
+    Instruction {
+        name: "MULC",
+        opcode: 37,
+        operand: OperandKind::None,
+        func: |vm, _operand| arithmetic_with_mode(vm, ArithmeticOp::Mul, OverflowMode::Checked),
+    },
+    Instruction {
+        name: "ADDS",
+        opcode: 38,
+        operand: OperandKind::None,
+        func: |vm, _operand| arithmetic_with_mode(vm, ArithmeticOp::Add, OverflowMode::Saturating),
+    },
+    Instruction {
+        name: "SUBS",
+        opcode: 39,
+        operand: OperandKind::None,
+        func: |vm, _operand| arithmetic_with_mode(vm, ArithmeticOp::Sub, OverflowMode::Saturating),
+    },
+    Instruction {
+        name: "MULS",
+        opcode: 40,
+        operand: OperandKind::None,
+        func: |vm, _operand| arithmetic_with_mode(vm, ArithmeticOp::Mul, OverflowMode::Saturating),
+    },
+    Instruction {
+        name: "AND",
+        opcode: 41,
+        operand: OperandKind::None,
+        func: |vm, _operand| bitwise(vm, |a, b| Ok(a & b)),
+    },
+    Instruction {
+        name: "OR",
+        opcode: 42,
+        operand: OperandKind::None,
+        func: |vm, _operand| bitwise(vm, |a, b| Ok(a | b)),
+    },
+    Instruction {
+        name: "XOR",
+        opcode: 43,
+        operand: OperandKind::None,
+        func: |vm, _operand| bitwise(vm, |a, b| Ok(a ^ b)),
+    },
+    Instruction {
+        name: "NOT",
+        opcode: 44,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_int()?;
+            vm.push(Value::Int(!a))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "SHL",
+        opcode: 45,
+        operand: OperandKind::None,
+        func: |vm, _operand| bitwise(vm, |a, b| shift(b).map(|b| a.checked_shl(b).unwrap_or(0))),
+    },
+    Instruction {
+        name: "SHR",
+        opcode: 46,
+        operand: OperandKind::None,
+        func: |vm, _operand| bitwise(vm, |a, b| shift(b).map(|b| (a as u64).checked_shr(b).unwrap_or(0) as i64)),
+    },
+    Instruction {
+        name: "SAR",
+        opcode: 47,
+        operand: OperandKind::None,
+        func: |vm, _operand| bitwise(vm, |a, b| shift(b).map(|b| a >> b.min(63))),
+    },
+    Instruction {
+        name: "ROTL",
+        opcode: 48,
+        operand: OperandKind::None,
+        func: |vm, _operand| bitwise(vm, |a, b| Ok(a.rotate_left(b.rem_euclid(64) as u32))),
+    },
+    Instruction {
+        name: "ROTR",
+        opcode: 49,
+        operand: OperandKind::None,
+        func: |vm, _operand| bitwise(vm, |a, b| Ok(a.rotate_right(b.rem_euclid(64) as u32))),
+    },
+    Instruction {
+        name: "POPCNT",
+        opcode: 50,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_int()?;
+            vm.push(Value::Int(a.count_ones() as i64))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "CMP",
+        opcode: 51,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            let b = vm.pop()?;
+
+            let ordering = match (&b, &a) {
+                (Value::Str(b), Value::Str(a)) => b.cmp(a),
+                _ => match numeric_cmp(&b, &a) {
+                    Some(ordering) => ordering,
+                    None if b.as_f64().is_some() && a.as_f64().is_some() => return Err(VMError::Unordered),
+                    None if b.as_f64().is_some() => return Err(VMError::TypeMismatch { expected: "number", found: a.type_name() }),
+                    None => return Err(VMError::TypeMismatch { expected: "number", found: b.type_name() }),
+                },
+            };
+
+            vm.push(Value::Int(ordering as i64))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "RAT",
+        opcode: 52,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let denominator = whole(vm.pop()?)?;
+            let numerator = whole(vm.pop()?)?;
+
+            let a = Rational::new(numerator, denominator).ok_or(VMError::DivisionByZero)?;
+            vm.push(Value::Rational(Shared::new(a)))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "TORAT",
+        opcode: 53,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            let a = match to_rational(&a) {
+                Some(a) => a,
+                None => {
+                    let n = a.to_number()?;
+                    Rational::from_f64(n).ok_or(VMError::InvalidRational(n))?
+                }
+            };
+
+            vm.push(Value::Rational(Shared::new(a)))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "ITER",
+        opcode: 54,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_iter()?;
+            vm.push(Value::Iter(a))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "NEXT",
+        opcode: 55,
+        operand: OperandKind::Address,
+        func: |vm, operand| {
+            // the iterator stays on the stack for the next time round the loop
+            let iter = match vm.peek() {
+                Some(Value::Iter(iter)) => iter.clone(),
+                Some(a) => return Err(VMError::TypeMismatch { expected: "iterator", found: a.type_name() }),
+                None => return Err(VMError::StackUnderflow),
+            };
+
+            match iter.next() {
+                Some(a) => vm.push(a)?,
+                None => {
+                    vm.pop()?;
+                    vm.branch(operand.ok_or(VMError::MissingOperand)?.to_number()?)?;
+                }
+            }
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "RANGE",
+        opcode: 56,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let end = to_index(vm.pop()?)?;
+            let start = to_index(vm.pop()?)?;
+
+            vm.push(Value::Iter(Shared::new(Iter::range(start, end))))?;
+            Ok(())
         }
     },
-];
\ No newline at end of file
+    Instruction {
+        name: "NEWLIST",
+        opcode: 80,
+        operand: OperandKind::Immediate,
+        func: |vm, operand| {
+            let n = operand.ok_or(VMError::MissingOperand)?.to_number()?;
+            let count = usize::try_from(number_to_int(n)?).map_err(|_| VMError::InvalidInteger(n))?;
+
+            // checked before anything's allocated, so a huge count is an underflow rather than the allocation failing
+            if count > vm.execution().stack().len() {
+                return Err(VMError::StackUnderflow);
+            }
+
+            let mut values = Vec::with_capacity(count);
+            for _ in 0..count {
+                values.push(vm.pop()?);
+            }
+            values.reverse();
+
+            vm.push(Value::List(Shared::new(List::new(values))))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "LPUSH",
+        opcode: 81,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            let list = vm.pop()?.to_list()?;
+            list.push(a);
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "LPOP",
+        opcode: 82,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_list()?.pop()?;
+            vm.push(a)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "LGET",
+        opcode: 83,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let index = to_index(vm.pop()?)?;
+            let a = vm.pop()?.to_list()?.get(index)?;
+            vm.push(a)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "LSET",
+        opcode: 84,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            let index = to_index(vm.pop()?)?;
+            vm.pop()?.to_list()?.set(index, a)
+        }
+    },
+    Instruction {
+        name: "LLEN",
+        opcode: 85,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_list()?.len();
+            vm.push(Value::Int(a as i64))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "LSLICE",
+        opcode: 86,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let end = to_index(vm.pop()?)?;
+            let start = to_index(vm.pop()?)?;
+            let a = vm.pop()?.to_list()?.slice(start, end)?;
+
+            vm.push(Value::List(Shared::new(a)))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "LCONCAT",
+        opcode: 87,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_list()?;
+            let b = vm.pop()?.to_list()?;
+
+            let mut values = b.to_vec();
+            values.extend(a.to_vec());
+
+            vm.push(Value::List(Shared::new(List::new(values))))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "NEWMAP",
+        opcode: 88,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            vm.push(Value::Map(Shared::new(Map::new())))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "MGET",
+        opcode: 89,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let key = vm.pop()?;
+            let a = vm.pop()?.to_map()?.get(&key)?.ok_or(VMError::MissingKey(key))?;
+            vm.push(a)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "MSET",
+        opcode: 90,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?;
+            let key = vm.pop()?;
+            vm.pop()?.to_map()?.set(key, a)
+        }
+    },
+    Instruction {
+        name: "MHAS",
+        opcode: 91,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let key = vm.pop()?;
+            let a = vm.pop()?.to_map()?.contains(&key)?;
+            vm.push(Value::Bool(a))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "MDEL",
+        opcode: 92,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let key = vm.pop()?;
+            vm.pop()?.to_map()?.remove(&key)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "MKEYS",
+        opcode: 93,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_map()?.keys();
+            vm.push(Value::List(Shared::new(List::new(a))))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "MLEN",
+        opcode: 94,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_map()?.len();
+            vm.push(Value::Int(a as i64))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "BALLOC",
+        opcode: 110,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let length = to_index(vm.pop()?)?;
+            let length = usize::try_from(length).map_err(|_| VMError::InvalidInteger(length as OperandSize))?;
+
+            // the length comes from the program, so too big for memory is an error it can catch rather than an abort
+            let mut bytes = Vec::new();
+            bytes.try_reserve_exact(length).map_err(|_| VMError::OutOfMemory(length))?;
+            bytes.resize(length, 0);
+
+            vm.push(Value::Bytes(Shared::new(Bytes::new(bytes))))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "BGET",
+        opcode: 111,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let index = to_index(vm.pop()?)?;
+            let a = vm.pop()?.to_bytes()?.get(index)?;
+            vm.push(Value::Int(a as i64))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "BSET",
+        opcode: 112,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = to_index(vm.pop()?)?;
+            let a = u8::try_from(a).map_err(|_| VMError::InvalidByte(a))?;
+            let index = to_index(vm.pop()?)?;
+            vm.pop()?.to_bytes()?.set(index, a)
+        }
+    },
+    Instruction {
+        name: "BLEN",
+        opcode: 113,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_bytes()?.len();
+            vm.push(Value::Int(a as i64))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "BSLICE",
+        opcode: 114,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let end = to_index(vm.pop()?)?;
+            let start = to_index(vm.pop()?)?;
+            let a = vm.pop()?.to_bytes()?.slice(start, end)?;
+
+            vm.push(Value::Bytes(Shared::new(a)))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "BCONCAT",
+        opcode: 115,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_bytes()?;
+            let b = vm.pop()?.to_bytes()?;
+
+            let mut bytes = b.to_vec();
+            bytes.extend(a.to_vec());
+
+            vm.push(Value::Bytes(Shared::new(Bytes::new(bytes))))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "BTOSTR",
+        opcode: 116,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_bytes()?.to_vec();
+            let a = String::from_utf8(a).map_err(|err| VMError::InvalidUtf8 { offset: err.utf8_error().valid_up_to() })?;
+
+            vm.push(Value::Str(intern_string(a)))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "STRTOB",
+        opcode: 117,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = vm.pop()?.to_str()?.as_bytes().to_vec();
+            vm.push(Value::Bytes(Shared::new(Bytes::new(a))))?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "BREADILE",
+        opcode: 118,
+        operand: OperandKind::Immediate,
+        func: |vm, operand| read_bytes(vm, operand, ReadKind::Int, false),
+    },
+    Instruction {
+        name: "BREADIBE",
+        opcode: 119,
+        operand: OperandKind::Immediate,
+        func: |vm, operand| read_bytes(vm, operand, ReadKind::Int, true),
+    },
+    Instruction {
+        name: "BREADULE",
+        opcode: 120,
+        operand: OperandKind::Immediate,
+        func: |vm, operand| read_bytes(vm, operand, ReadKind::Uint, false),
+    },
+    Instruction {
+        name: "BREADUBE",
+        opcode: 121,
+        operand: OperandKind::Immediate,
+        func: |vm, operand| read_bytes(vm, operand, ReadKind::Uint, true),
+    },
+    Instruction {
+        name: "BREADFLE",
+        opcode: 122,
+        operand: OperandKind::Immediate,
+        func: |vm, operand| read_bytes(vm, operand, ReadKind::Float, false),
+    },
+    Instruction {
+        name: "BREADFBE",
+        opcode: 123,
+        operand: OperandKind::Immediate,
+        func: |vm, operand| read_bytes(vm, operand, ReadKind::Float, true),
+    },
+];
+
+// the arithmetic instructions that pick their own overflow mode rather than using the vm's
+fn arithmetic_with_mode(vm: &mut VM, op: ArithmeticOp, mode: OverflowMode) -> Result<(), VMError> {
+    let a = vm.pop()?;
+    let b = vm.pop()?;
+
+    vm.push(arithmetic(op, b, a, mode)?)?;
+    Ok(())
+}
+
+// the two operand bitwise instructions, which only work on ints
+// a is the second value on the stack and b the top, so SHL shifts a by b
+fn bitwise(vm: &mut VM, op: fn(i64, i64) -> Result<i64, VMError>) -> Result<(), VMError> {
+    let b = vm.pop()?.to_int()?;
+    let a = vm.pop()?.to_int()?;
+
+    vm.push(Value::Int(op(a, b)?))?;
+    Ok(())
+}
+
+// a shift amount, which can't be negative but can be as big as it likes
+fn shift(amount: i64) -> Result<u32, VMError> {
+    match amount {
+        ..0 => Err(VMError::InvalidShift(amount)),
+        _ => Ok(amount.min(u32::MAX as i64) as u32),
+    }
+}
+
+// an int or bigint as a bigint, for the parts of a rational
+fn whole(value: Value) -> Result<BigInt, VMError> {
+    match value {
+        Value::Int(n) => Ok(BigInt::from(n)),
+        Value::BigInt(n) => Ok(n.as_ref().clone()),
+        value => Err(VMError::TypeMismatch { expected: "int", found: value.type_name() }),
+    }
+}
+
+// an index (or a length or range end), which can be an int or a whole number since PUSH only pushes numbers
+fn to_index(value: Value) -> Result<i64, VMError> {
+    match value {
+        Value::Int(n) => Ok(n),
+        value => number_to_int(value.to_number()?),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ReadKind {
+    Int,
+    Uint,
+    Float,
+}
+
+// the BREAD instructions, the operand is how many bytes to read
+fn read_bytes(vm: &mut VM, operand: Option<Value>, kind: ReadKind, big_endian: bool) -> Result<(), VMError> {
+    let width = operand.ok_or(VMError::MissingOperand)?.to_number()?;
+    let size = match (kind, width) {
+        (ReadKind::Int | ReadKind::Uint, 1.0 | 2.0 | 4.0 | 8.0) | (ReadKind::Float, 4.0 | 8.0) => width as usize,
+        _ => return Err(VMError::InvalidWidth(width)),
+    };
+
+    let offset = to_index(vm.pop()?)?;
+    let mut bytes = vm.pop()?.to_bytes()?.read_at(offset, size)?;
+
+    // always big endian from here on, so the top byte's first
+    if !big_endian {
+        bytes.reverse();
+    }
+
+    // ints are widened to 8 bytes, filling with the sign bit if they're signed
+    let fill = match kind {
+        ReadKind::Int if bytes[0] & 0x80 != 0 => 0xff,
+        _ => 0,
+    };
+    let mut widened = [fill; 8];
+    widened[8 - size..].copy_from_slice(&bytes);
+
+    let a = match kind {
+        ReadKind::Float if size == 4 => Value::Number(f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as OperandSize),
+        ReadKind::Float => Value::Number(OperandSize::from_be_bytes(widened)),
+        ReadKind::Int => Value::Int(i64::from_be_bytes(widened)),
+        ReadKind::Uint => match i64::try_from(u64::from_be_bytes(widened)) {
+            Ok(n) => Value::Int(n),
+            Err(_) => Value::from(BigInt::from_bytes(false, &u64::from_be_bytes(widened).to_le_bytes())),
+        },
+    };
+
+    vm.push(a)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsm::vm::RunState;
+
+    // runs a b <instruction> and hands back what it left on top, mode is the vm's if it's given one
+    fn run(name: &str, a: i64, b: i64, mode: Option<OverflowMode>) -> Result<Value, VMError> {
+        let opcode = DEFAULT_INSTRUCTION_SET.iter().find(|instruction| instruction.name == name).unwrap().opcode;
+        let code = vec![
+            RawInstruction { opcode: 12, operand: Some(0.0) }, // PUSHC
+            RawInstruction { opcode: 12, operand: Some(1.0) },
+            RawInstruction { opcode, operand: None },
+        ];
+
+        let mut vm = VM::new(DEFAULT_INSTRUCTION_SET.to_vec(), Some(code), Some(vec![Value::Int(a), Value::Int(b)]), None);
+        if let Some(mode) = mode {
+            vm.set_overflow_mode(mode);
+        }
+
+        match vm.run() {
+            RunState::Halted => Ok(vm.execution().stack().last().unwrap().clone()),
+            RunState::Error(err) => Err(err),
+            state => panic!("unexpected {:?}", state),
+        }
+    }
+
+    fn int(result: Result<Value, VMError>) -> i64 {
+        match result {
+            Ok(Value::Int(n)) => n,
+            result => panic!("expected an int, got {:?}", result),
+        }
+    }
+
+    fn overflowed(result: Result<Value, VMError>) -> bool {
+        matches!(result, Err(VMError::IntegerOverflow(_)))
+    }
+
+    #[test]
+    fn add_sub_mul_are_checked_by_default() {
+        assert!(overflowed(run("ADD", i64::MAX, 1, None)));
+        assert!(overflowed(run("SUB", i64::MIN, 1, None)));
+        assert!(overflowed(run("MUL", i64::MAX, 2, None)));
+        assert_eq!(int(run("SUB", 10, 3, None)), 7);
+    }
+
+    #[test]
+    fn add_sub_mul_follow_the_vms_mode() {
+        let wrapping = Some(OverflowMode::Wrapping);
+        assert_eq!(int(run("ADD", i64::MAX, 1, wrapping)), i64::MIN);
+        assert_eq!(int(run("SUB", i64::MIN, 1, wrapping)), i64::MAX);
+        assert_eq!(int(run("MUL", i64::MAX, 2, wrapping)), -2);
+
+        let saturating = Some(OverflowMode::Saturating);
+        assert_eq!(int(run("ADD", i64::MAX, 1, saturating)), i64::MAX);
+        assert_eq!(int(run("SUB", i64::MIN, 1, saturating)), i64::MIN);
+        assert_eq!(int(run("MUL", i64::MIN, 2, saturating)), i64::MIN);
+    }
+
+    #[test]
+    fn wrapping_instructions_ignore_the_vms_mode() {
+        let mode = Some(OverflowMode::Saturating);
+        assert_eq!(int(run("ADDW", i64::MAX, 1, mode)), i64::MIN);
+        assert_eq!(int(run("SUBW", i64::MIN, 1, mode)), i64::MAX);
+        assert_eq!(int(run("MULW", i64::MAX, 2, mode)), -2);
+    }
+
+    #[test]
+    fn checked_instructions_ignore_the_vms_mode() {
+        let mode = Some(OverflowMode::Wrapping);
+        assert!(overflowed(run("ADDC", i64::MAX, 1, mode)));
+        assert!(overflowed(run("SUBC", i64::MIN, 1, mode)));
+        assert!(overflowed(run("MULC", i64::MAX, 2, mode)));
+        assert_eq!(int(run("ADDC", 2, 3, mode)), 5);
+    }
+
+    #[test]
+    fn saturating_instructions_ignore_the_vms_mode() {
+        let mode = Some(OverflowMode::Wrapping);
+        assert_eq!(int(run("ADDS", i64::MAX, 1, mode)), i64::MAX);
+        assert_eq!(int(run("SUBS", i64::MIN, 1, mode)), i64::MIN);
+        assert_eq!(int(run("MULS", i64::MAX, -2, mode)), i64::MIN);
+    }
+
+    #[test]
+    fn min_mod_minus_one_is_zero_by_default() {
+        assert_eq!(int(run("MOD", i64::MIN, -1, None)), 0);
+    }
+}
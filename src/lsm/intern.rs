@@ -0,0 +1,101 @@
+// one copy of every string the vm makes, so equal strings share storage and comparing them is comparing pointers
+// there's one table for the whole process with the `sync` feature, otherwise one per thread since Rc can't cross threads
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use crate::lsm::shared::Shared;
+
+// how big the table has to get before strings nothing else holds are dropped from it
+const MIN_PRUNE_SIZE: usize = 1024;
+
+// hashed and compared by contents, so it can be looked up by a &str
+struct Entry(Shared<String>);
+
+impl Borrow<str> for Entry {
+    fn borrow(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl Hash for Entry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state)
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Entry {}
+
+#[derive(Default)]
+pub struct Interner {
+    strings: HashSet<Entry>,
+    prune_at: usize,
+}
+
+impl Interner {
+    pub fn intern(&mut self, string: &str) -> Shared<String> {
+        match self.strings.get(string) {
+            Some(entry) => entry.0.clone(),
+            None => self.insert(string.to_string()),
+        }
+    }
+
+    // the same as intern, but hangs on to the string instead of copying it if it's new
+    pub fn intern_string(&mut self, string: String) -> Shared<String> {
+        match self.strings.get(string.as_str()) {
+            Some(entry) => entry.0.clone(),
+            None => self.insert(string),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    fn insert(&mut self, string: String) -> Shared<String> {
+        // every so often forget the strings only we are holding, waiting until the table's doubled keeps it cheap
+        if self.strings.len() >= self.prune_at.max(MIN_PRUNE_SIZE) {
+            self.strings.retain(|entry| Shared::strong_count(&entry.0) > 1);
+            self.prune_at = self.strings.len() * 2;
+        }
+
+        let shared = Shared::new(string);
+        self.strings.insert(Entry(shared.clone()));
+        shared
+    }
+}
+
+#[cfg(not(feature = "sync"))]
+thread_local! {
+    static INTERNER: std::cell::RefCell<Interner> = std::cell::RefCell::new(Interner::default());
+}
+
+#[cfg(feature = "sync")]
+static INTERNER: std::sync::LazyLock<std::sync::Mutex<Interner>> = std::sync::LazyLock::new(Default::default);
+
+// runs f with the shared table
+fn with_interner<T>(f: impl FnOnce(&mut Interner) -> T) -> T {
+    #[cfg(not(feature = "sync"))]
+    return INTERNER.with(|interner| f(&mut interner.borrow_mut()));
+
+    #[cfg(feature = "sync")]
+    return f(&mut INTERNER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+}
+
+// the shared copy of the string, every equal string interned anywhere is the same pointer
+pub fn intern(string: &str) -> Shared<String> {
+    with_interner(|interner| interner.intern(string))
+}
+
+pub fn intern_string(string: String) -> Shared<String> {
+    with_interner(|interner| interner.intern_string(string))
+}
@@ -0,0 +1,94 @@
+// what ITER makes and NEXT steps through, so a loop over a collection doesn't need its own index
+// shared like a list is, so every copy of an iterator is at the same place
+use std::fmt;
+use crate::lsm::bytes::Bytes;
+use crate::lsm::intern::intern;
+use crate::lsm::list::List;
+use crate::lsm::shared::{Lock, Shared};
+use crate::lsm::vm::Value;
+
+enum Source {
+    Str { string: Shared<String>, position: usize }, // position is a byte offset, always on a char boundary
+    // lists and bytes are gone through as they are when NEXT gets to them, so changes part way through are seen
+    List { list: Shared<List>, index: usize },
+    Bytes { bytes: Shared<Bytes>, index: usize },
+    // a map's keys are taken when the iterator's made, so the order can't change under it
+    Keys { keys: Vec<Value>, index: usize },
+    Range { next: i64, end: i64 },
+}
+
+pub struct Iter {
+    source: Lock<Source>,
+}
+
+impl Iter {
+    // each char of the string, as a string
+    pub fn string(string: Shared<String>) -> Iter {
+        Iter::new(Source::Str { string, position: 0 })
+    }
+
+    pub fn list(list: Shared<List>) -> Iter {
+        Iter::new(Source::List { list, index: 0 })
+    }
+
+    // each byte as an int
+    pub fn bytes(bytes: Shared<Bytes>) -> Iter {
+        Iter::new(Source::Bytes { bytes, index: 0 })
+    }
+
+    pub fn keys(keys: Vec<Value>) -> Iter {
+        Iter::new(Source::Keys { keys, index: 0 })
+    }
+
+    // ints from start up to but not including end
+    pub fn range(start: i64, end: i64) -> Iter {
+        Iter::new(Source::Range { next: start, end })
+    }
+
+    fn new(source: Source) -> Iter {
+        Iter { source: Lock::new(source) }
+    }
+
+    // the next value, or None once there are none left
+    pub fn next(&self) -> Option<Value> {
+        let mut source = self.source.borrow_mut();
+
+        match &mut *source {
+            Source::Str { string, position } => {
+                let c = string[*position..].chars().next()?;
+                *position += c.len_utf8();
+                Some(Value::Str(intern(c.encode_utf8(&mut [0; 4]))))
+            }
+            Source::List { list, index } => {
+                let value = list.get(*index as i64).ok()?;
+                *index += 1;
+                Some(value)
+            }
+            Source::Bytes { bytes, index } => {
+                let byte = bytes.get(*index as i64).ok()?;
+                *index += 1;
+                Some(Value::Int(byte as i64))
+            }
+            Source::Keys { keys, index } => {
+                let key = keys.get(*index)?.clone();
+                *index += 1;
+                Some(key)
+            }
+            Source::Range { next, end } => {
+                if next >= end {
+                    return None;
+                }
+
+                // next is below end, so this can't overflow
+                *next += 1;
+                Some(Value::Int(*next - 1))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Iter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<iterator>")
+    }
+}
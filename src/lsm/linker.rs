@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use crate::lsm::bytecode::{Export, Object};
+use crate::lsm::instruction::{Instruction, OpcodeSize, OperandKind, RawInstruction};
+use crate::lsm::vm::OperandSize;
+
+// objects are referred to by their position in the list given to link
+#[derive(Clone, Debug)]
+pub enum LinkError {
+    DuplicateSymbol { name: String, first: usize, second: usize },
+    UnresolvedSymbol { name: String, object: usize },
+    InvalidReference { name: String, object: usize, address: usize }, // an import pointing outside of the object's code
+    InvalidExport { name: String, object: usize, address: usize }, // an export pointing outside of the object's code
+    InvalidConstKey { key: OperandSize, object: usize, address: usize }, // a const key that isn't one of the object's consts
+    IllegalInstruction { opcode: OpcodeSize, object: usize },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol { name, first, second } => write!(f, "symbol {} is exported by both object {} and object {}", name, first, second),
+            LinkError::UnresolvedSymbol { name, object } => write!(f, "unresolved symbol {} imported by object {}", name, object),
+            LinkError::InvalidReference { name, object, address } => write!(f, "object {} references {} at address {}, which is outside of its code", object, name, address),
+            LinkError::InvalidExport { name, object, address } => write!(f, "object {} exports {} at address {}, which is outside of its code", object, name, address),
+            LinkError::InvalidConstKey { key, object, address } => write!(f, "object {} uses const key {} at address {}, which isn't one of its consts", object, key, address),
+            LinkError::IllegalInstruction { opcode, object } => write!(f, "illegal instruction {} in object {}", opcode, object),
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+// links the objects into one program, relocating code addresses and const keys and resolving imports
+// the first object's code comes first, so that's where execution starts
+pub fn link(objects: &[Object], instruction_set: &[Instruction]) -> Result<Object, LinkError> {
+    // first we lay out the code so we know where every export ends up
+    let mut symbols: HashMap<&str, (usize, usize)> = HashMap::new(); // name -> (object, final address)
+    let mut code_offsets = Vec::with_capacity(objects.len());
+    let mut code_offset = 0;
+
+    for (index, object) in objects.iter().enumerate() {
+        code_offsets.push(code_offset);
+
+        for export in object.exports.iter() {
+            if export.address >= object.code.len() {
+                return Err(LinkError::InvalidExport { name: export.name.clone(), object: index, address: export.address });
+            }
+
+            if let Some((first, _)) = symbols.get(export.name.as_str()) {
+                return Err(LinkError::DuplicateSymbol { name: export.name.clone(), first: *first, second: index });
+            }
+
+            symbols.insert(&export.name, (index, code_offset + export.address));
+        }
+
+        code_offset += object.code.len();
+    }
+
+    // then copy everything over, shifting operands by where their object ended up
+    let mut linked = Object::default();
+
+    for (index, object) in objects.iter().enumerate() {
+        let code_offset = code_offsets[index];
+        let const_offset = linked.consts.len();
+
+        for (address, raw_instruction) in object.code.iter().enumerate() {
+            let instruction = instruction_set.iter()
+                .find(|instruction| instruction.opcode == raw_instruction.opcode)
+                .ok_or(LinkError::IllegalInstruction { opcode: raw_instruction.opcode, object: index })?;
+
+            let operand = match (instruction.operand, raw_instruction.operand) {
+                (OperandKind::Address, Some(address)) => Some(address + code_offset as OperandSize),
+                // shifting a key that's out of range would quietly point it at another object's const
+                (OperandKind::ConstKey, Some(key)) if key < 0.0 || key.fract() != 0.0 || key >= object.consts.len() as OperandSize => {
+                    return Err(LinkError::InvalidConstKey { key, object: index, address });
+                }
+                (OperandKind::ConstKey, Some(key)) => Some(key + const_offset as OperandSize),
+                (_, operand) => operand,
+            };
+
+            linked.code.push(RawInstruction { opcode: raw_instruction.opcode, operand });
+        }
+
+        // imports get patched last, overwriting whatever placeholder the object had
+        for import in object.imports.iter() {
+            let (_, address) = symbols.get(import.name.as_str())
+                .ok_or_else(|| LinkError::UnresolvedSymbol { name: import.name.clone(), object: index })?;
+
+            for reference in import.references.iter() {
+                if *reference >= object.code.len() {
+                    return Err(LinkError::InvalidReference { name: import.name.clone(), object: index, address: *reference });
+                }
+
+                linked.code[code_offset + reference].operand = Some(*address as OperandSize);
+            }
+        }
+
+        linked.consts.extend(object.consts.iter().cloned());
+
+        // debug info is kept as long as at least one object has some
+        if let Some(debug) = &object.debug {
+            linked.debug.get_or_insert_with(Default::default).append(debug, code_offset);
+        }
+
+        linked.exports.extend(object.exports.iter().map(|export| Export { name: export.name.clone(), address: code_offset + export.address }));
+    }
+
+    Ok(linked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsm::instruction::DEFAULT_INSTRUCTION_SET;
+    use crate::lsm::vm::Value;
+
+    fn pushc(key: OperandSize) -> RawInstruction {
+        RawInstruction { opcode: 12, operand: Some(key) }
+    }
+
+    fn object(code: Vec<RawInstruction>, consts: Vec<Value>, exports: Vec<Export>) -> Object {
+        Object { code, consts, exports, ..Object::default() }
+    }
+
+    fn export(name: &str, address: usize) -> Export {
+        Export { name: name.to_string(), address }
+    }
+
+    #[test]
+    fn exports_and_const_keys_are_shifted() {
+        let objects = [
+            object(vec![pushc(0.0)], vec![Value::Number(1.0)], vec![export("first", 0)]),
+            object(vec![pushc(1.0), pushc(0.0)], vec![Value::Number(2.0), Value::Number(3.0)], vec![export("second", 1)]),
+        ];
+
+        let linked = link(&objects, DEFAULT_INSTRUCTION_SET).unwrap();
+        let operands: Vec<_> = linked.code.iter().map(|instruction| instruction.operand).collect();
+        assert_eq!(operands, [Some(0.0), Some(2.0), Some(1.0)]);
+        assert_eq!(linked.consts.len(), 3);
+        assert_eq!(linked.exports.iter().map(|export| export.address).collect::<Vec<_>>(), [0, 2]);
+    }
+
+    #[test]
+    fn an_export_outside_of_the_code_is_an_error() {
+        let objects = [
+            object(vec![pushc(0.0)], vec![Value::Nil], Vec::new()),
+            object(vec![pushc(0.0)], vec![Value::Nil], vec![export("end", 1)]),
+        ];
+
+        let result = link(&objects, DEFAULT_INSTRUCTION_SET);
+        assert!(matches!(result, Err(LinkError::InvalidExport { object: 1, address: 1, .. })));
+    }
+
+    #[test]
+    fn a_const_key_outside_of_the_consts_is_an_error() {
+        for key in [1.0, -1.0, 0.5] {
+            let objects = [
+                object(vec![pushc(0.0)], vec![Value::Nil], Vec::new()),
+                object(vec![pushc(0.0), pushc(key)], vec![Value::Nil], Vec::new()),
+            ];
+
+            let result = link(&objects, DEFAULT_INSTRUCTION_SET);
+            assert!(matches!(result, Err(LinkError::InvalidConstKey { object: 1, address: 1, .. })), "{}", key);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+// a growable list of values, shared between everything holding it so changes through one are seen by all of them
+// indexes are checked, anything out of range is an error a TRY can catch rather than a panic
+use std::cell::RefCell;
+use std::fmt;
+use crate::lsm::error::VMError;
+use crate::lsm::shared::Lock;
+use crate::lsm::vm::Value;
+
+pub struct List {
+    values: Lock<Vec<Value>>,
+}
+
+impl List {
+    pub fn new(values: Vec<Value>) -> List {
+        List { values: Lock::new(values) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.borrow().is_empty()
+    }
+
+    pub fn get(&self, index: i64) -> Result<Value, VMError> {
+        let values = self.values.borrow();
+        let index = check_index(index, values.len())?;
+        Ok(values[index].clone())
+    }
+
+    pub fn set(&self, index: i64, value: Value) -> Result<(), VMError> {
+        let mut values = self.values.borrow_mut();
+        let index = check_index(index, values.len())?;
+        values[index] = value;
+        Ok(())
+    }
+
+    pub fn push(&self, value: Value) {
+        self.values.borrow_mut().push(value);
+    }
+
+    pub fn pop(&self) -> Result<Value, VMError> {
+        self.values.borrow_mut().pop().ok_or(VMError::EmptyList)
+    }
+
+    // the values from start up to but not including end, as a new list
+    pub fn slice(&self, start: i64, end: i64) -> Result<List, VMError> {
+        let values = self.values.borrow();
+
+        match (usize::try_from(start), usize::try_from(end)) {
+            (Ok(s), Ok(e)) if s <= e && e <= values.len() => Ok(List::new(values[s..e].to_vec())),
+            _ => Err(VMError::InvalidSlice { start, end, len: values.len() }),
+        }
+    }
+
+    // a copy of the values, so nothing's held locked while they're used
+    // (the same list can be on both sides of LCONCAT, which would deadlock a mutex)
+    pub fn to_vec(&self) -> Vec<Value> {
+        self.values.borrow().clone()
+    }
+
+    // a copy that shares nothing with this one, nested lists and bytes are copied too
+    // only for lists that can't contain themselves, like the ones loaded as consts
+    pub fn deep_copy(&self) -> List {
+        List::new(self.values.borrow().iter().map(Value::deep_copy).collect())
+    }
+
+    // writes the list with each value formatted by write_value, and separator between them
+    fn write(&self, f: &mut fmt::Formatter<'_>, separator: &str, write_value: fn(&Value, &mut fmt::Formatter<'_>) -> fmt::Result) -> fmt::Result {
+        write_once(f, self, "[...]", |f| {
+            write!(f, "[")?;
+
+            for (i, value) in self.values.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, "{}", separator)?;
+                }
+                write_value(value, f)?;
+            }
+
+            write!(f, "]")
+        })
+    }
+}
+
+thread_local! {
+    // the lists and maps being written on this thread right now, outermost first
+    static WRITING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+// writes a list or map with write, unless it's already being written further out, when it's the placeholder instead
+// so one inside itself doesn't go round forever (or lock itself twice)
+pub(crate) fn write_once<T>(f: &mut fmt::Formatter<'_>, item: &T, placeholder: &str, write: impl FnOnce(&mut fmt::Formatter<'_>) -> fmt::Result) -> fmt::Result {
+    let address = item as *const T as usize;
+
+    if WRITING.with(|writing| writing.borrow().contains(&address)) {
+        return write!(f, "{}", placeholder);
+    }
+
+    WRITING.with(|writing| writing.borrow_mut().push(address));
+    let result = write(f);
+    WRITING.with(|writing| writing.borrow_mut().pop());
+
+    result
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, ", ", fmt::Debug::fmt)
+    }
+}
+
+// the same as a list literal in assembly, which is separated by spaces
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, " ", fmt::Display::fmt)
+    }
+}
+
+// an index that's in range for something len long
+fn check_index(index: i64, len: usize) -> Result<usize, VMError> {
+    match usize::try_from(index) {
+        Ok(i) if i < len => Ok(i),
+        _ => Err(VMError::IndexOutOfBounds { index, len }),
+    }
+}
@@ -0,0 +1,148 @@
+// values looked up by key, shared between everything holding it like a list is
+// keys are numbers, ints, strings, bools or nil, and keys that are EQ are the same key, so 1 and 1i are one entry
+// entries stay in the order they were first set, so going through a map gives the same order every run
+use std::collections::HashMap;
+use std::fmt;
+use crate::lsm::error::VMError;
+use crate::lsm::list::write_once;
+use crate::lsm::shared::{Lock, Shared};
+use crate::lsm::vm::{OperandSize, Value};
+
+// a key as it's hashed, whole numbers are stored as ints so they hash the same as the int they're equal to
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Int(i64),
+    Number(u64), // the bits of a number that isn't whole, or is too big for an int
+    Str(Shared<String>),
+    Bool(bool),
+    Nil,
+}
+
+impl Key {
+    fn new(value: &Value) -> Result<Key, VMError> {
+        match value {
+            Value::Int(n) => Ok(Key::Int(*n)),
+            // nan isn't equal to anything, itself included, so it could be set but never got
+            Value::Number(n) if n.is_nan() => Err(VMError::InvalidKey("nan")),
+            // -0 ends up as 0 here too, which is right since they're equal
+            Value::Number(n) if n.fract() == 0.0 && *n >= i64::MIN as OperandSize && *n < i64::MAX as OperandSize => Ok(Key::Int(*n as i64)),
+            Value::Number(n) => Ok(Key::Number(n.to_bits())),
+            Value::Str(s) => Ok(Key::Str(s.clone())),
+            Value::Bool(b) => Ok(Key::Bool(*b)),
+            Value::Nil => Ok(Key::Nil),
+            value => Err(VMError::InvalidKey(value.type_name())),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    indexes: HashMap<Key, usize>, // where each key's entry is in values
+    values: Vec<(Value, Value)>, // the key as it was first set and its value, in the order they were set
+}
+
+#[derive(Default)]
+pub struct Map {
+    entries: Lock<Entries>,
+}
+
+impl Map {
+    pub fn new() -> Map {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().values.is_empty()
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<Value>, VMError> {
+        let key = Key::new(key)?;
+        let entries = self.entries.borrow();
+        Ok(entries.indexes.get(&key).map(|index| entries.values[*index].1.clone()))
+    }
+
+    pub fn contains(&self, key: &Value) -> Result<bool, VMError> {
+        let key = Key::new(key)?;
+        Ok(self.entries.borrow().indexes.contains_key(&key))
+    }
+
+    // setting a key that's already there changes its value but keeps its place
+    pub fn set(&self, key: Value, value: Value) -> Result<(), VMError> {
+        let hashed = Key::new(&key)?;
+        let mut entries = self.entries.borrow_mut();
+
+        match entries.indexes.get(&hashed) {
+            Some(&index) => entries.values[index].1 = value,
+            None => {
+                let index = entries.values.len();
+                entries.indexes.insert(hashed, index);
+                entries.values.push((key, value));
+            }
+        }
+
+        Ok(())
+    }
+
+    // takes the key out and hands back its value, None if it wasn't there
+    pub fn remove(&self, key: &Value) -> Result<Option<Value>, VMError> {
+        let key = Key::new(key)?;
+        let mut entries = self.entries.borrow_mut();
+
+        let Some(index) = entries.indexes.remove(&key) else {
+            return Ok(None);
+        };
+
+        // everything after it moves down one to keep the order
+        let (_, value) = entries.values.remove(index);
+        for i in entries.indexes.values_mut() {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+
+        Ok(Some(value))
+    }
+
+    // the keys in order
+    pub fn keys(&self) -> Vec<Value> {
+        self.entries.borrow().values.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    // a copy of the keys and values in order, so nothing's held locked while they're used
+    pub fn to_vec(&self) -> Vec<(Value, Value)> {
+        self.entries.borrow().values.clone()
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, write_value: fn(&Value, &mut fmt::Formatter<'_>) -> fmt::Result) -> fmt::Result {
+        write_once(f, self, "{...}", |f| {
+            write!(f, "{{")?;
+
+            for (i, (key, value)) in self.entries.borrow().values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_value(key, f)?;
+                write!(f, ": ")?;
+                write_value(value, f)?;
+            }
+
+            write!(f, "}}")
+        })
+    }
+}
+
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, fmt::Debug::fmt)
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, fmt::Display::fmt)
+    }
+}
@@ -0,0 +1,211 @@
+// maths intrinsics, backed by rust's f64 methods
+// they're a set of their own so an instruction set only gets them if it wants them
+use std::cmp::Ordering;
+use crate::lsm::arithmetic::{numeric_cmp, OverflowMode};
+use crate::lsm::bigint::BigInt;
+use crate::lsm::error::VMError;
+use crate::lsm::instruction::{Instruction, OperandKind};
+use crate::lsm::rational::Rational;
+use crate::lsm::shared::Shared;
+use crate::lsm::vm::{OperandSize, ToNumber, Value, VM};
+
+/*
+instruction set for reference
+NAME - OPCODE - DETAILS
+SQRT - 60 - pops a number and pushes its square root
+POW - 61 - raises the second number on the stack to the power of the first
+EXP - 62 - pops a number and pushes e to the power of it
+LN - 63 - pops a number and pushes its natural logarithm
+LOG10 - 64 - pops a number and pushes its base 10 logarithm
+SIN - 65 - pops a number of radians and pushes its sine
+COS - 66 - pops a number of radians and pushes its cosine
+TAN - 67 - pops a number of radians and pushes its tangent
+ATAN2 - 68 - pushes the angle of the point (x, y), where x is the top of the stack and y the second
+FLOOR - 69 - rounds the number on top of the stack down, an int or bigint stays as it is and a rational gives an int or bigint
+CEIL - 70 - rounds the number on top of the stack up, an int or bigint stays as it is and a rational gives an int or bigint
+ROUND - 71 - rounds the number on top of the stack to the nearest whole number, halves away from zero, an int or bigint stays as it is and a rational gives an int or bigint
+ABS - 72 - pops a number, int, bigint or rational and pushes its absolute value, abs of the smallest int follows the vm's overflow mode
+MIN - 73 - pushes the smaller of the two topmost values, two ints, bigints or rationals give whichever one it was
+MAX - 74 - pushes the bigger of the two topmost values, two ints, bigints or rationals give whichever one it was
+ISNAN - 75 - pops a number and pushes whether it's nan
+ISINF - 76 - pops a number and pushes whether it's infinite, either way
+ISFINITE - 77 - pops a number and pushes whether it's neither nan nor infinite
+ */
+
+// ints, bigints and rationals are turned into numbers for anything but FLOOR, CEIL, ROUND, ABS, MIN and MAX
+pub const MATH_INSTRUCTION_SET: &[Instruction] = &[
+    Instruction {
+        name: "SQRT",
+        opcode: 60,
+        operand: OperandKind::None,
+        func: |vm, _operand| unary(vm, OperandSize::sqrt),
+    },
+    Instruction {
+        name: "POW",
+        opcode: 61,
+        operand: OperandKind::None,
+        func: |vm, _operand| binary(vm, OperandSize::powf),
+    },
+    Instruction {
+        name: "EXP",
+        opcode: 62,
+        operand: OperandKind::None,
+        func: |vm, _operand| unary(vm, OperandSize::exp),
+    },
+    Instruction {
+        name: "LN",
+        opcode: 63,
+        operand: OperandKind::None,
+        func: |vm, _operand| unary(vm, OperandSize::ln),
+    },
+    Instruction {
+        name: "LOG10",
+        opcode: 64,
+        operand: OperandKind::None,
+        func: |vm, _operand| unary(vm, OperandSize::log10),
+    },
+    Instruction {
+        name: "SIN",
+        opcode: 65,
+        operand: OperandKind::None,
+        func: |vm, _operand| unary(vm, OperandSize::sin),
+    },
+    Instruction {
+        name: "COS",
+        opcode: 66,
+        operand: OperandKind::None,
+        func: |vm, _operand| unary(vm, OperandSize::cos),
+    },
+    Instruction {
+        name: "TAN",
+        opcode: 67,
+        operand: OperandKind::None,
+        func: |vm, _operand| unary(vm, OperandSize::tan),
+    },
+    Instruction {
+        name: "ATAN2",
+        opcode: 68,
+        operand: OperandKind::None,
+        func: |vm, _operand| binary(vm, OperandSize::atan2),
+    },
+    Instruction {
+        name: "FLOOR",
+        opcode: 69,
+        operand: OperandKind::None,
+        func: |vm, _operand| rounding(vm, OperandSize::floor, Rational::floor),
+    },
+    Instruction {
+        name: "CEIL",
+        opcode: 70,
+        operand: OperandKind::None,
+        func: |vm, _operand| rounding(vm, OperandSize::ceil, Rational::ceil),
+    },
+    Instruction {
+        name: "ROUND",
+        opcode: 71,
+        operand: OperandKind::None,
+        func: |vm, _operand| rounding(vm, OperandSize::round, Rational::round),
+    },
+    Instruction {
+        name: "ABS",
+        opcode: 72,
+        operand: OperandKind::None,
+        func: |vm, _operand| {
+            let a = match vm.pop()? {
+                Value::Int(n) => match (n.checked_abs(), vm.overflow_mode()) {
+                    (Some(n), _) => Value::Int(n),
+                    (None, OverflowMode::Wrapping) => Value::Int(n.wrapping_abs()),
+                    (None, OverflowMode::Saturating) => Value::Int(i64::MAX),
+                    (None, OverflowMode::Checked) => return Err(VMError::IntegerOverflow("ABS")),
+                    (None, OverflowMode::Promote) => Value::from(BigInt::from(n).abs()),
+                },
+                Value::BigInt(n) => Value::from(n.abs()),
+                Value::Rational(n) => Value::Rational(Shared::new(n.abs())),
+                a => Value::Number(a.to_number()?.abs()),
+            };
+
+            vm.push(a)?;
+            Ok(())
+        }
+    },
+    Instruction {
+        name: "MIN",
+        opcode: 73,
+        operand: OperandKind::None,
+        func: |vm, _operand| min_max(vm, Ordering::Less, OperandSize::min),
+    },
+    Instruction {
+        name: "MAX",
+        opcode: 74,
+        operand: OperandKind::None,
+        func: |vm, _operand| min_max(vm, Ordering::Greater, OperandSize::max),
+    },
+    Instruction {
+        name: "ISNAN",
+        opcode: 75,
+        operand: OperandKind::None,
+        func: |vm, _operand| check(vm, OperandSize::is_nan),
+    },
+    Instruction {
+        name: "ISINF",
+        opcode: 76,
+        operand: OperandKind::None,
+        func: |vm, _operand| check(vm, OperandSize::is_infinite),
+    },
+    Instruction {
+        name: "ISFINITE",
+        opcode: 77,
+        operand: OperandKind::None,
+        func: |vm, _operand| check(vm, OperandSize::is_finite),
+    },
+];
+
+fn unary(vm: &mut VM, op: fn(OperandSize) -> OperandSize) -> Result<(), VMError> {
+    let a = vm.pop()?.to_number()?;
+    vm.push(Value::Number(op(a)))?;
+    Ok(())
+}
+
+// a is the second value on the stack and b the top, so POW is a to the power of b
+fn binary(vm: &mut VM, op: fn(OperandSize, OperandSize) -> OperandSize) -> Result<(), VMError> {
+    let b = vm.pop()?.to_number()?;
+    let a = vm.pop()?.to_number()?;
+
+    vm.push(Value::Number(op(a, b)))?;
+    Ok(())
+}
+
+// an int or bigint is already whole, so it's left alone, and a rational's rounded exactly to one
+fn rounding(vm: &mut VM, op: fn(OperandSize) -> OperandSize, exact: fn(&Rational) -> BigInt) -> Result<(), VMError> {
+    let a = match vm.pop()? {
+        a @ (Value::Int(_) | Value::BigInt(_)) => a,
+        Value::Rational(a) => Value::from(exact(&a)),
+        a => Value::Number(op(a.to_number()?)),
+    };
+
+    vm.push(a)?;
+    Ok(())
+}
+
+// ints, bigints and rationals are compared exactly so nothing's lost past 2^53, keep is the ordering of a against b that keeps a
+fn min_max(vm: &mut VM, keep: Ordering, number_op: fn(OperandSize, OperandSize) -> OperandSize) -> Result<(), VMError> {
+    let b = vm.pop()?;
+    let a = vm.pop()?;
+
+    let result = match (&a, &b) {
+        (Value::Int(_) | Value::BigInt(_) | Value::Rational(_), Value::Int(_) | Value::BigInt(_) | Value::Rational(_)) => match numeric_cmp(&a, &b) {
+            Some(ordering) if ordering == keep => a,
+            _ => b,
+        },
+        _ => Value::Number(number_op(a.to_number()?, b.to_number()?)),
+    };
+
+    vm.push(result)?;
+    Ok(())
+}
+
+fn check(vm: &mut VM, op: fn(OperandSize) -> bool) -> Result<(), VMError> {
+    let a = vm.pop()?.to_number()?;
+    vm.push(Value::Bool(op(a)))?;
+    Ok(())
+}
@@ -1,6 +1,51 @@
 mod stack;
 mod instruction;
 mod vm;
+mod error;
+mod coroutine;
+mod shared;
+#[cfg(feature = "sync")]
+mod actor;
+mod bytecode;
+mod assembler;
+mod linker;
+mod profiler;
+mod tracer;
+mod debug;
+mod program;
+mod const_pool;
+mod intern;
+mod arithmetic;
+mod math;
+mod bigint;
+mod rational;
+mod list;
+mod map;
+mod bytes;
+mod iter;
 
 pub use vm::*;
-pub use instruction::*;
\ No newline at end of file
+pub use error::*;
+pub use coroutine::*;
+pub use shared::*;
+#[cfg(feature = "sync")]
+pub use actor::*;
+pub use instruction::*;
+pub use stack::*;
+pub use bytecode::*;
+pub use assembler::*;
+pub use linker::*;
+pub use profiler::*;
+pub use tracer::*;
+pub use debug::*;
+pub use program::*;
+pub use const_pool::*;
+pub use intern::*;
+pub use arithmetic::*;
+pub use math::*;
+pub use bigint::*;
+pub use rational::*;
+pub use list::*;
+pub use map::*;
+pub use bytes::*;
+pub use iter::*;
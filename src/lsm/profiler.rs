@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+use crate::lsm::instruction::OpcodeSize;
+
+// instructions that we keep taken/not-taken counts for
+const CONDITIONAL_BRANCHES: &[&str] = &["BRZ", "BRP"];
+
+#[derive(Clone, Debug, Default)]
+pub struct OpcodeProfile {
+    pub name: &'static str,
+    pub count: u64,
+    pub time: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BranchProfile {
+    pub name: &'static str,
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl BranchProfile {
+    // fraction of executions where the branch was taken
+    pub fn taken_ratio(&self) -> f64 {
+        let total = self.taken + self.not_taken;
+
+        if total == 0 {
+            0.0
+        } else {
+            self.taken as f64 / total as f64
+        }
+    }
+}
+
+// everything recorded while the vm runs with profiling enabled
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub address_counts: Vec<u64>, // indexed by virtual address
+    pub opcodes: BTreeMap<OpcodeSize, OpcodeProfile>,
+    pub branches: BTreeMap<usize, BranchProfile>, // keyed by the address of the branch
+    pub max_stack_depth: usize,
+}
+
+impl Profile {
+    pub fn new(code_size: usize) -> Profile {
+        Profile { address_counts: vec![0; code_size], ..Profile::default() }
+    }
+
+    // records one executed instruction
+    pub fn record(&mut self, address: usize, opcode: OpcodeSize, name: &'static str, time: Duration, branched: bool, stack_depth: usize) {
+        if address >= self.address_counts.len() {
+            self.address_counts.resize(address + 1, 0);
+        }
+        self.address_counts[address] += 1;
+
+        let opcode_profile = self.opcodes.entry(opcode).or_insert_with(|| OpcodeProfile { name, ..OpcodeProfile::default() });
+        opcode_profile.count += 1;
+        opcode_profile.time += time;
+
+        if CONDITIONAL_BRANCHES.contains(&name) {
+            let branch_profile = self.branches.entry(address).or_insert_with(|| BranchProfile { name, ..BranchProfile::default() });
+
+            if branched {
+                branch_profile.taken += 1;
+            } else {
+                branch_profile.not_taken += 1;
+            }
+        }
+
+        self.max_stack_depth = self.max_stack_depth.max(stack_depth);
+    }
+
+    // total amount of instructions executed
+    pub fn instructions_executed(&self) -> u64 {
+        self.address_counts.iter().sum()
+    }
+
+    // machine readable version of the profile, as a single json object
+    pub fn to_json(&self) -> String {
+        let addresses: Vec<String> = self.address_counts.iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(address, count)| format!("{{\"address\":{},\"count\":{}}}", address, count))
+            .collect();
+
+        let opcodes: Vec<String> = self.opcodes.iter()
+            .map(|(opcode, profile)| format!(
+                "{{\"opcode\":{},\"name\":\"{}\",\"count\":{},\"time_ns\":{}}}",
+                opcode, profile.name, profile.count, profile.time.as_nanos()
+            ))
+            .collect();
+
+        let branches: Vec<String> = self.branches.iter()
+            .map(|(address, profile)| format!(
+                "{{\"address\":{},\"name\":\"{}\",\"taken\":{},\"not_taken\":{},\"taken_ratio\":{}}}",
+                address, profile.name, profile.taken, profile.not_taken, profile.taken_ratio()
+            ))
+            .collect();
+
+        format!(
+            "{{\"instructions_executed\":{},\"max_stack_depth\":{},\"addresses\":[{}],\"opcodes\":[{}],\"branches\":[{}]}}",
+            self.instructions_executed(), self.max_stack_depth, addresses.join(","), opcodes.join(","), branches.join(",")
+        )
+    }
+}
+
+// human readable version of the profile, as a set of tables
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "instructions executed: {}", self.instructions_executed())?;
+        writeln!(f, "max stack depth: {}", self.max_stack_depth)?;
+
+        writeln!(f)?;
+        writeln!(f, "{:<8} {:>6} {:>12} {:>14} {:>12}", "NAME", "OPCODE", "COUNT", "TIME (ns)", "AVG (ns)")?;
+        for (opcode, profile) in self.opcodes.iter() {
+            let time = profile.time.as_nanos();
+            writeln!(f, "{:<8} {:>6} {:>12} {:>14} {:>12}", profile.name, opcode, profile.count, time, time / profile.count.max(1) as u128)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<8} {:>12}", "ADDRESS", "COUNT")?;
+        for (address, count) in self.address_counts.iter().enumerate() {
+            if *count > 0 {
+                writeln!(f, "{:<8} {:>12}", address, count)?;
+            }
+        }
+
+        if !self.branches.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "{:<8} {:<8} {:>12} {:>12} {:>8}", "ADDRESS", "BRANCH", "TAKEN", "NOT TAKEN", "RATIO")?;
+            for (address, profile) in self.branches.iter() {
+                writeln!(f, "{:<8} {:<8} {:>12} {:>12} {:>8.3}", address, profile.name, profile.taken, profile.not_taken, profile.taken_ratio())?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::Read;
+use crate::lsm::bytecode::{BytecodeError, Object};
+use crate::lsm::debug::DebugInfo;
+use crate::lsm::instruction::{Instruction, RawInstruction};
+use crate::lsm::shared::Shared;
+use crate::lsm::vm::Value;
+
+// bytecode that's been decoded and checked once, ready to be run by any number of vms
+// it never changes while running, everything a run changes lives in the vm's Execution
+// everything's shared, so cloning it or handing it to a vm doesn't copy the code
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    code: Shared<Vec<RawInstruction>>,
+    consts: Shared<Vec<Value>>, // the read-only part of the const pool, keyed by index
+    debug: Option<Shared<DebugInfo>>,
+}
+
+impl Program {
+    pub fn new(code: Vec<RawInstruction>, consts: Vec<Value>) -> Program {
+        Program { code: Shared::new(code), consts: Shared::new(consts), debug: None }
+    }
+
+    // decodes bytecode of any version, the same as Object::decode but ready to run
+    pub fn decode(bytecode: &[u8], instruction_set: &[Instruction]) -> Result<Program, BytecodeError> {
+        Program::from_object(Object::decode(bytecode, instruction_set)?)
+    }
+
+    // decodes bytecode as it's read, only ever buffering a little of it at a time
+    pub fn from_reader(source: impl Read, instruction_set: &[Instruction]) -> Result<Program, BytecodeError> {
+        Program::from_object(Object::from_reader(source, instruction_set)?)
+    }
+
+    // an object can only be run once everything it imports has been linked in
+    pub fn from_object(object: Object) -> Result<Program, BytecodeError> {
+        if let Some(import) = object.imports.into_iter().next() {
+            return Err(BytecodeError::UnresolvedImport(import.name));
+        }
+
+        Ok(Program {
+            code: Shared::new(object.code),
+            consts: Shared::new(object.consts),
+            debug: object.debug.map(Shared::new),
+        })
+    }
+
+    pub fn code(&self) -> &[RawInstruction] {
+        &self.code
+    }
+
+    pub fn consts(&self) -> &Shared<Vec<Value>> {
+        &self.consts
+    }
+
+    pub fn debug(&self) -> Option<&DebugInfo> {
+        self.debug.as_deref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty() && self.consts.is_empty()
+    }
+
+    // puts another program's code after this one's, its constants are keyed from 0 and replace any already there
+    // anything else sharing this program keeps the old one
+    pub fn append(&mut self, other: &Program) {
+        if self.is_empty() {
+            *self = other.clone();
+            return;
+        }
+
+        // the debug info's addresses are relative to the appended code, which goes after what's already here
+        if let Some(debug) = &other.debug {
+            let code_offset = self.code.len();
+            Shared::make_mut(self.debug.get_or_insert_with(Default::default)).append(debug, code_offset);
+        }
+
+        Shared::make_mut(&mut self.code).extend_from_slice(&other.code);
+        let consts = Shared::make_mut(&mut self.consts);
+        for (key, value) in other.consts.iter().enumerate() {
+            match consts.get_mut(key) {
+                Some(existing) => *existing = value.clone(),
+                None => consts.push(value.clone()),
+            }
+        }
+    }
+}
@@ -0,0 +1,304 @@
+// exact fractions, a bigint over a bigint so nothing's ever rounded or overflows
+// always kept in lowest terms with a positive denominator, so every value has one form and comparing the parts is enough
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use crate::lsm::bigint::BigInt;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numerator: BigInt,
+    denominator: BigInt, // never zero or negative
+}
+
+impl Rational {
+    // None when the denominator's zero
+    pub fn new(numerator: BigInt, denominator: BigInt) -> Option<Rational> {
+        if denominator.is_zero() {
+            return None;
+        }
+
+        let divisor = numerator.gcd(&denominator);
+        let divisor = match denominator.is_negative() {
+            true => -&divisor,
+            false => divisor,
+        };
+
+        Some(Rational {
+            numerator: numerator.divmod(&divisor)?.0,
+            denominator: denominator.divmod(&divisor)?.0,
+        })
+    }
+
+    pub fn numerator(&self) -> &BigInt {
+        &self.numerator
+    }
+
+    pub fn denominator(&self) -> &BigInt {
+        &self.denominator
+    }
+
+    pub fn is_whole(&self) -> bool {
+        self.denominator == BigInt::from(1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numerator.is_zero()
+    }
+
+    // exactly the value of the f64, every finite one is a fraction with a power of 2 underneath
+    // None for nan and the infinities
+    pub fn from_f64(n: f64) -> Option<Rational> {
+        if !n.is_finite() {
+            return None;
+        }
+
+        let bits = n.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64;
+        let mantissa = bits & ((1 << 52) - 1);
+
+        // subnormals have no hidden bit and the same exponent as the smallest normal
+        let (mantissa, exponent) = match exponent {
+            0 => (mantissa, -1074),
+            _ => (mantissa | (1 << 52), exponent - 1075),
+        };
+
+        let mantissa = BigInt::from(mantissa as i64);
+        let mantissa = if n.is_sign_negative() { -&mantissa } else { mantissa };
+        let one = BigInt::from(1);
+
+        match exponent {
+            0.. => Rational::new(&mantissa << exponent as usize, one),
+            _ => Rational::new(mantissa, &one << exponent.unsigned_abs() as usize),
+        }
+    }
+
+    // as close as an f64 gets
+    // dividing the parts as f64s would give inf/inf for big ones, so the quotient's worked out to 64 bits and scaled back down
+    pub fn to_f64(&self) -> f64 {
+        let mut scale = 64 + self.denominator.bits() as i64 - self.numerator.bits() as i64;
+
+        let quotient = match scale {
+            0.. => (&self.numerator << scale as usize).divmod(&self.denominator),
+            _ => self.numerator.divmod(&(&self.denominator << scale.unsigned_abs() as usize)),
+        };
+        let mut n = quotient.expect("denominators are never zero").0.to_f64();
+
+        // a step at a time, since 2^scale might not fit in an f64 even when the answer does
+        while scale != 0 {
+            let step = scale.clamp(-1000, 1000);
+            n *= 2f64.powi(-step as i32);
+            scale -= step;
+        }
+
+        n
+    }
+
+    // None when dividing by zero
+    pub fn checked_div(&self, other: &Rational) -> Option<Rational> {
+        Rational::new(&self.numerator * &other.denominator, &self.denominator * &other.numerator)
+    }
+
+    // what's left after taking away a whole number of others, truncating like i64's %, so it takes the sign of self
+    // None when dividing by zero
+    pub fn checked_rem(&self, other: &Rational) -> Option<Rational> {
+        let quotient = self.checked_div(other)?;
+        let whole = quotient.numerator.divmod(&quotient.denominator)?.0;
+        Some(self - &(other * &Rational::from(whole)))
+    }
+
+    // the nearest whole number at or below it
+    pub fn floor(&self) -> BigInt {
+        let (quotient, remainder) = self.numerator.divmod(&self.denominator).expect("denominators are never zero");
+
+        // the quotient's truncated, which is one too high for a negative with anything left over
+        match remainder.is_negative() {
+            true => &quotient - &BigInt::from(1),
+            false => quotient,
+        }
+    }
+
+    // the nearest whole number at or above it
+    pub fn ceil(&self) -> BigInt {
+        let (quotient, remainder) = self.numerator.divmod(&self.denominator).expect("denominators are never zero");
+
+        match !remainder.is_zero() && !remainder.is_negative() {
+            true => &quotient + &BigInt::from(1),
+            false => quotient,
+        }
+    }
+
+    // the nearest whole number, halves away from zero the same as f64::round
+    pub fn round(&self) -> BigInt {
+        let half = Rational { numerator: BigInt::from(1), denominator: BigInt::from(2) };
+        let rounded = (&self.abs() + &half).floor();
+
+        match self.numerator.is_negative() {
+            true => -&rounded,
+            false => rounded,
+        }
+    }
+
+    pub fn abs(&self) -> Rational {
+        Rational { numerator: self.numerator.abs(), denominator: self.denominator.clone() }
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational { numerator: -&self.numerator, denominator: self.denominator.clone() }
+    }
+}
+
+impl Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, other: &Rational) -> Rational {
+        let numerator = &(&self.numerator * &other.denominator) + &(&other.numerator * &self.denominator);
+        Rational::new(numerator, &self.denominator * &other.denominator).expect("denominators are never zero")
+    }
+}
+
+impl Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, other: &Rational) -> Rational {
+        self + &-other
+    }
+}
+
+impl Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, other: &Rational) -> Rational {
+        let numerator = &self.numerator * &other.numerator;
+        Rational::new(numerator, &self.denominator * &other.denominator).expect("denominators are never zero")
+    }
+}
+
+impl From<BigInt> for Rational {
+    fn from(n: BigInt) -> Rational {
+        Rational { numerator: n, denominator: BigInt::from(1) }
+    }
+}
+
+impl From<i64> for Rational {
+    fn from(n: i64) -> Rational {
+        Rational::from(BigInt::from(n))
+    }
+}
+
+// both denominators are positive, so cross multiplying keeps the order
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        (&self.numerator * &other.denominator).cmp(&(&other.numerator * &self.denominator))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// numerator/denominator, even when it's whole, so it's always clear it's a fraction
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+impl fmt::Debug for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rat(numerator: i64, denominator: i64) -> Rational {
+        Rational::new(BigInt::from(numerator), BigInt::from(denominator)).unwrap()
+    }
+
+    fn same(rational: Rational, expected: &str) {
+        assert_eq!(rational.to_string(), expected);
+    }
+
+    #[test]
+    fn new_reduces_and_keeps_the_denominator_positive() {
+        same(rat(6, 4), "3/2");
+        same(rat(6, -4), "-3/2");
+        same(rat(-6, -4), "3/2");
+        same(rat(-6, 4), "-3/2");
+        same(rat(0, -5), "0/1");
+        same(rat(7, 7), "1/1");
+        assert!(rat(-7, -7).is_whole());
+        assert!(!rat(0, -5).numerator().is_negative());
+    }
+
+    #[test]
+    fn a_zero_denominator_is_none() {
+        assert!(Rational::new(BigInt::from(1), BigInt::from(0)).is_none());
+        assert!(Rational::new(BigInt::from(0), BigInt::from(0)).is_none());
+        assert!(rat(1, 2).checked_div(&rat(0, 1)).is_none());
+        assert!(rat(1, 2).checked_rem(&rat(0, 1)).is_none());
+    }
+
+    #[test]
+    fn every_op_gives_lowest_terms() {
+        same(&rat(1, 6) + &rat(1, 3), "1/2");
+        same(&rat(1, 2) + &rat(1, 2), "1/1");
+        same(&rat(1, 2) - &rat(1, 6), "1/3");
+        same(&rat(1, 3) - &rat(1, 3), "0/1");
+        same(&rat(2, 3) * &rat(3, 4), "1/2");
+        same(&rat(-2, 3) * &rat(-3, 2), "1/1");
+        same(rat(2, 3).checked_div(&rat(4, 9)).unwrap(), "3/2");
+        same(rat(2, 3).checked_div(&rat(-4, 9)).unwrap(), "-3/2");
+        same(rat(7, 2).checked_rem(&rat(1, 1)).unwrap(), "1/2");
+        same(rat(9, 4).checked_rem(&rat(3, 4)).unwrap(), "0/1");
+    }
+
+    #[test]
+    fn signs() {
+        same(-&rat(1, 2), "-1/2");
+        same(-&rat(-1, 2), "1/2");
+        same(rat(-3, 4).abs(), "3/4");
+        same(rat(-7, 2).checked_rem(&rat(1, 1)).unwrap(), "-1/2");
+        same(rat(7, 2).checked_rem(&rat(-1, 1)).unwrap(), "1/2");
+        assert!(rat(-1, 2) < rat(1, 3));
+        assert!(rat(-1, 2) < rat(-1, 3));
+        assert_eq!(rat(2, -4), rat(-1, 2));
+    }
+
+    #[test]
+    fn floor_ceil_and_round_match_f64() {
+        // small enough that the f64 division is exact, or near enough that it rounds the same way
+        for numerator in -20..=20 {
+            for denominator in [1, 2, 3, 4, 5, -2, -3, -7] {
+                let exact = rat(numerator, denominator);
+                let n = numerator as f64 / denominator as f64;
+
+                assert_eq!(exact.floor(), BigInt::from(n.floor() as i64), "floor {}", exact);
+                assert_eq!(exact.ceil(), BigInt::from(n.ceil() as i64), "ceil {}", exact);
+                assert_eq!(exact.round(), BigInt::from(n.round() as i64), "round {}", exact);
+            }
+        }
+    }
+
+    #[test]
+    fn round_takes_halves_away_from_zero() {
+        for (numerator, rounded) in [(1, 1), (-1, -1), (3, 2), (-3, -2), (5, 3), (-5, -3)] {
+            assert_eq!(rat(numerator, 2).round(), BigInt::from(rounded), "{}/2", numerator);
+        }
+
+        // either side of a half goes to the nearest, whatever the sign
+        assert_eq!(rat(-49, 100).round(), BigInt::from(0));
+        assert_eq!(rat(-51, 100).round(), BigInt::from(-1));
+        assert_eq!(rat(-1, 3).floor(), BigInt::from(-1));
+        assert_eq!(rat(-1, 3).ceil(), BigInt::from(0));
+    }
+}
@@ -0,0 +1,83 @@
+// how values share their contents
+// by default that's Rc/RefCell, with the `sync` feature it's Arc/Mutex so values (and the vm) can cross threads
+use std::fmt;
+
+#[cfg(not(feature = "sync"))]
+use std::cell::{Ref, RefCell, RefMut};
+#[cfg(feature = "sync")]
+use std::sync::{Mutex, MutexGuard};
+
+#[cfg(not(feature = "sync"))]
+pub type Shared<T> = std::rc::Rc<T>;
+#[cfg(feature = "sync")]
+pub type Shared<T> = std::sync::Arc<T>;
+
+// anything boxed up inside the vm (e.g. tracers) has to be Send with the `sync` feature
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+#[cfg(feature = "sync")]
+pub trait MaybeSend: Send {}
+#[cfg(feature = "sync")]
+impl<T: ?Sized + Send> MaybeSend for T {}
+
+// interior mutability for shared values, with the same interface either way
+#[cfg(not(feature = "sync"))]
+pub struct Lock<T>(RefCell<T>);
+#[cfg(feature = "sync")]
+pub struct Lock<T>(Mutex<T>);
+
+#[cfg(not(feature = "sync"))]
+impl<T> Lock<T> {
+    pub fn new(value: T) -> Lock<T> {
+        Lock(RefCell::new(value))
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.0.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.0.borrow_mut()
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<T> Lock<T> {
+    pub fn new(value: T) -> Lock<T> {
+        Lock(Mutex::new(value))
+    }
+
+    // a mutex can't tell readers from writers, so both of these lock it
+    // a panic on another thread doesn't leave a value half-changed, so poisoning is ignored
+    pub fn borrow(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn borrow_mut(&self) -> MutexGuard<'_, T> {
+        self.borrow()
+    }
+}
+
+impl<T: Default> Default for Lock<T> {
+    fn default() -> Lock<T> {
+        Lock::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Lock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // printing something that's currently borrowed shouldn't panic (or deadlock)
+        #[cfg(not(feature = "sync"))]
+        let value = self.0.try_borrow().ok();
+        #[cfg(feature = "sync")]
+        let value = self.0.try_lock().ok();
+
+        match value {
+            Some(value) => value.fmt(f),
+            None => write!(f, "<borrowed>"),
+        }
+    }
+}
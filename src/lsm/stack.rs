@@ -8,11 +8,13 @@ impl<T> Stack<T> {
     pub fn new(stack_size: usize) -> Stack<T> {
         Stack { stack: Vec::with_capacity(stack_size), size: stack_size }
     }
-    pub fn push(&mut self, item: T) {
+    // hands the item back if the stack is full
+    pub fn push(&mut self, item: T) -> Result<(), T> {
         if self.stack.len() >= self.size {
-            panic!("stack overflow");
+            return Err(item);
         }
         self.stack.push(item);
+        Ok(())
     }
 
     pub fn pop(&mut self) -> Option<T> {
@@ -30,4 +32,18 @@ impl<T> Stack<T> {
     pub fn is_empty(&self) -> bool {
         self.stack.is_empty()
     }
+
+    // drops everything above the given length
+    pub fn truncate(&mut self, len: usize) {
+        self.stack.truncate(len);
+    }
+
+    // the most items the stack can hold
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        self.stack.as_slice()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,185 @@
+use std::io::Write;
+use std::ops::Range;
+use crate::lsm::debug::SourceLocation;
+use crate::lsm::instruction::OpcodeSize;
+use crate::lsm::shared::MaybeSend;
+use crate::lsm::vm::{OperandSize, Value};
+
+// a single executed instruction, as seen by a tracer
+pub struct TraceEvent<'a> {
+    pub address: usize,
+    pub opcode: OpcodeSize,
+    pub name: &'static str,
+    pub operand: Option<OperandSize>,
+    pub location: Option<SourceLocation<'a>>, // where the instruction came from, if the code has debug info
+    pub stack_before: &'a [Value],
+    pub stack_after: &'a [Value],
+}
+
+// hook invoked by the run loop for every executed instruction
+pub trait Tracer: MaybeSend {
+    // checked before the stack is captured, so tracers that filter don't pay for the copy
+    fn wants(&self, _address: usize, _opcode: OpcodeSize) -> bool {
+        true
+    }
+
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+// limits which instructions get traced, by address range and/or opcode
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    pub addresses: Option<Range<usize>>,
+    pub opcodes: Option<Vec<OpcodeSize>>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, address: usize, opcode: OpcodeSize) -> bool {
+        let address_matches = match &self.addresses {
+            Some(range) => range.contains(&address),
+            None => true,
+        };
+
+        let opcode_matches = match &self.opcodes {
+            Some(opcodes) => opcodes.contains(&opcode),
+            None => true,
+        };
+
+        address_matches && opcode_matches
+    }
+}
+
+// wraps another tracer so it only sees instructions matching the filter
+pub struct FilteredTracer<T: Tracer> {
+    inner: T,
+    filter: TraceFilter,
+}
+
+impl<T: Tracer> FilteredTracer<T> {
+    pub fn new(inner: T, filter: TraceFilter) -> FilteredTracer<T> {
+        FilteredTracer { inner, filter }
+    }
+}
+
+impl<T: Tracer> Tracer for FilteredTracer<T> {
+    fn wants(&self, address: usize, opcode: OpcodeSize) -> bool {
+        self.filter.matches(address, opcode) && self.inner.wants(address, opcode)
+    }
+
+    fn trace(&mut self, event: &TraceEvent) {
+        self.inner.trace(event);
+    }
+}
+
+// one human readable line per instruction
+// e.g. 0004  BRZ    6          [5, 0] -> [5]  ; main.lsma:6:5
+pub struct TextTracer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(out: W) -> TextTracer<W> {
+        TextTracer { out }
+    }
+}
+
+impl<W: Write + MaybeSend> Tracer for TextTracer<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        let operand = match event.operand {
+            Some(operand) => operand.to_string(),
+            None => String::new(),
+        };
+
+        let before: Vec<String> = event.stack_before.iter().map(|value| value.to_string()).collect();
+        let after: Vec<String> = event.stack_after.iter().map(|value| value.to_string()).collect();
+
+        let location = match event.location {
+            Some(location) => format!("  ; {}", location),
+            None => String::new(),
+        };
+
+        // tracing should never bring the vm down, so a failed write is just dropped
+        let _ = writeln!(self.out, "{:04}  {:<6} {:<10} [{}] -> [{}]{}", event.address, event.name, operand, before.join(", "), after.join(", "), location);
+    }
+}
+
+// one json object per line per instruction
+pub struct JsonTracer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(out: W) -> JsonTracer<W> {
+        JsonTracer { out }
+    }
+}
+
+impl<W: Write + MaybeSend> Tracer for JsonTracer<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        let operand = match event.operand {
+            Some(operand) => json_number(operand),
+            None => "null".to_string(),
+        };
+
+        let before: Vec<String> = event.stack_before.iter().map(json_value).collect();
+        let after: Vec<String> = event.stack_after.iter().map(json_value).collect();
+
+        // the location fields are only there when the code has debug info
+        let location = match event.location {
+            Some(location) => format!(",\"file\":{},\"line\":{},\"column\":{}", json_string(location.file), location.line, location.column),
+            None => String::new(),
+        };
+
+        let _ = writeln!(
+            self.out,
+            "{{\"pc\":{},\"opcode\":{},\"mnemonic\":\"{}\",\"operand\":{},\"before\":[{}],\"after\":[{}]{}}}",
+            event.address, event.opcode, event.name, operand, before.join(","), after.join(","), location
+        );
+    }
+}
+
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Number(n) => json_number(*n),
+        Value::Int(n) => n.to_string(),
+        Value::BigInt(n) => n.to_string(),
+        Value::Rational(n) => json_string(&n.to_string()),
+        Value::Str(s) => json_string(s),
+        Value::List(list) => json_string(&list.to_string()),
+        Value::Map(map) => json_string(&map.to_string()),
+        Value::Bytes(bytes) => json_string(&bytes.to_string()),
+        Value::Iter(_) => json_string("<iterator>"),
+        Value::Bool(b) => b.to_string(),
+        Value::Nil => "null".to_string(),
+        Value::Coroutine(coroutine) => json_string(&format!("<coroutine {}>", coroutine.borrow().status.name())),
+    }
+}
+
+// json has no nan or infinity, so those go out as strings
+fn json_number(n: OperandSize) -> String {
+    if n.is_finite() {
+        n.to_string()
+    } else {
+        json_string(&n.to_string())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
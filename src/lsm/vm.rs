@@ -1,290 +1,542 @@
-use std::collections::HashMap;
-use std::rc::Rc;
-use crate::lsm::instruction::{Instruction, RawInstruction, OpcodeSize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::Read;
+use std::mem;
+use std::time::Instant;
+#[cfg(feature = "sync")]
+use crate::lsm::actor::ActorHandle;
+use crate::lsm::bytecode::{BytecodeError, Object};
+use crate::lsm::arithmetic::{numeric_cmp, OverflowMode};
+use crate::lsm::bigint::BigInt;
+use crate::lsm::bytes::Bytes;
+use crate::lsm::const_pool::{ConstHandle, ConstPool};
+use crate::lsm::coroutine::{Coroutine, CoroutineStatus};
+use crate::lsm::debug::DebugInfo;
+use crate::lsm::error::VMError;
+use crate::lsm::intern::intern_string;
+use crate::lsm::iter::Iter;
+use crate::lsm::instruction::{Instruction, OperandKind, RawInstruction, OpcodeSize};
+use crate::lsm::list::List;
+use crate::lsm::map::Map;
+use crate::lsm::profiler::Profile;
+use crate::lsm::program::Program;
+use crate::lsm::rational::Rational;
+use crate::lsm::shared::{Lock, Shared};
+use crate::lsm::tracer::{TraceEvent, Tracer};
 use crate::lsm::stack::Stack;
-use crate::lsm::vm::Value::{Number, Str};
 
 const DEFAULT_STACK_SIZE: usize = 128; // artificial limit
-const BYTECODE_SIGNATURE: &str = "!LSM!";
-const BYTECODE_CONSTS_SIGNATURE: &str = "!CONSTS";
-const BYTECODE_INSTRUCTIONS_SIGNATURE: &str = "!INSTR";
 
 pub type OperandSize = f64;
-pub type ConstPool = HashMap<usize, Value>;
 
 #[derive(Clone, Debug)]
 pub enum Value {
     Number(OperandSize), // OperandSize bytes
-    Str(Rc<String>), // dynamic amount of bytes
+    Int(i64), // 8 bytes
+    BigInt(Shared<BigInt>), // dynamic amount of bytes, only ever made for something that doesn't fit in an int
+    Rational(Shared<Rational>), // dynamic amount of bytes
+    Str(Shared<String>), // dynamic amount of bytes
+    List(Shared<List>), // dynamic amount of bytes, shared so changing it through one copy changes it for every copy
+    Map(Shared<Map>), // dynamic amount of bytes, shared like a list, runtime only so can't be a const
+    Bytes(Shared<Bytes>), // dynamic amount of bytes, shared like a list
+    Iter(Shared<Iter>), // runtime only, can't be a const
     Bool(bool), // 1 byte
     Nil, // 1 byte
+    Coroutine(Shared<Lock<Coroutine>>), // runtime only, can't be a const
+}
+
+impl Value {
+    // name of the type, used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Int(_) => "int",
+            Value::BigInt(_) => "bigint",
+            Value::Rational(_) => "rational",
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Bytes(_) => "bytes",
+            Value::Iter(_) => "iterator",
+            Value::Bool(_) => "bool",
+            Value::Nil => "nil",
+            Value::Coroutine(_) => "coroutine",
+        }
+    }
+
+    pub fn to_str(self) -> Result<Shared<String>, VMError> {
+        match self {
+            Value::Str(s) => Ok(s),
+            value => Err(VMError::TypeMismatch { expected: "string", found: value.type_name() }),
+        }
+    }
+
+    // any numeric value as an f64, None for anything else
+    pub fn as_f64(&self) -> Option<OperandSize> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Int(n) => Some(*n as OperandSize),
+            Value::BigInt(n) => Some(n.to_f64()),
+            Value::Rational(n) => Some(n.to_f64()),
+            _ => None,
+        }
+    }
+
+    // the string form TOSTR gives, which is the literal form except strings aren't quoted
+    pub fn to_display_string(&self) -> Shared<String> {
+        match self {
+            Value::Str(s) => s.clone(),
+            Value::Int(n) => intern_string(n.to_string()),
+            Value::BigInt(n) => intern_string(n.to_string()),
+            value => intern_string(value.to_string()),
+        }
+    }
+
+    pub fn to_int(self) -> Result<i64, VMError> {
+        match self {
+            Value::Int(n) => Ok(n),
+            value => Err(VMError::TypeMismatch { expected: "int", found: value.type_name() }),
+        }
+    }
+
+    pub fn to_list(self) -> Result<Shared<List>, VMError> {
+        match self {
+            Value::List(list) => Ok(list),
+            value => Err(VMError::TypeMismatch { expected: "list", found: value.type_name() }),
+        }
+    }
+
+    pub fn to_map(self) -> Result<Shared<Map>, VMError> {
+        match self {
+            Value::Map(map) => Ok(map),
+            value => Err(VMError::TypeMismatch { expected: "map", found: value.type_name() }),
+        }
+    }
+
+    pub fn to_bytes(self) -> Result<Shared<Bytes>, VMError> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            value => Err(VMError::TypeMismatch { expected: "bytes", found: value.type_name() }),
+        }
+    }
+
+    // an iterator over the value, which for a map is its keys
+    // an iterator is its own iterator, so ITER on one hands it back
+    pub fn to_iter(self) -> Result<Shared<Iter>, VMError> {
+        match self {
+            Value::Iter(iter) => Ok(iter),
+            Value::Str(string) => Ok(Shared::new(Iter::string(string))),
+            Value::List(list) => Ok(Shared::new(Iter::list(list))),
+            Value::Bytes(bytes) => Ok(Shared::new(Iter::bytes(bytes))),
+            Value::Map(map) => Ok(Shared::new(Iter::keys(map.keys()))),
+            value => Err(VMError::TypeMismatch { expected: "string, list, bytes, map or iterator", found: value.type_name() }),
+        }
+    }
+
+    // a copy that shares nothing that can be changed with the original, other values are just cloned
+    // it can't be something that contains itself, the consts it's used for never are
+    pub fn deep_copy(&self) -> Value {
+        match self {
+            Value::List(list) => Value::List(Shared::new(list.deep_copy())),
+            Value::Bytes(bytes) => Value::Bytes(Shared::new(Bytes::new(bytes.to_vec()))),
+            value => value.clone(),
+        }
+    }
+
+    pub fn to_coroutine(self) -> Result<Shared<Lock<Coroutine>>, VMError> {
+        match self {
+            Value::Coroutine(coroutine) => Ok(coroutine),
+            value => Err(VMError::TypeMismatch { expected: "coroutine", found: value.type_name() }),
+        }
+    }
+}
+
+// interned strings are the same pointer when they're equal, so that's checked before their contents
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(_) | Value::Int(_) | Value::BigInt(_) | Value::Rational(_), Value::Number(_) | Value::Int(_) | Value::BigInt(_) | Value::Rational(_)) => {
+                numeric_cmp(self, other) == Some(Ordering::Equal)
+            }
+            (Value::Str(a), Value::Str(b)) => Shared::ptr_eq(a, b) || a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            // lists, maps and bytes can change, so two are only equal if they're the same one
+            (Value::List(a), Value::List(b)) => Shared::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Shared::ptr_eq(a, b),
+            (Value::Bytes(a), Value::Bytes(b)) => Shared::ptr_eq(a, b),
+            (Value::Iter(a), Value::Iter(b)) => Shared::ptr_eq(a, b),
+            (Value::Coroutine(a), Value::Coroutine(b)) => Shared::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+// how a value would be written in assembly, strings are quoted
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Int(n) => write!(f, "{}i", n),
+            Value::BigInt(n) => write!(f, "{}i", n),
+            Value::Rational(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{:?}", s.as_str()),
+            Value::List(list) => write!(f, "{}", list),
+            Value::Map(map) => write!(f, "{}", map),
+            Value::Bytes(bytes) => write!(f, "{}", bytes),
+            Value::Iter(_) => write!(f, "<iterator>"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Nil => write!(f, "nil"),
+            Value::Coroutine(coroutine) => write!(f, "<coroutine {}>", coroutine.borrow().status.name()),
+        }
+    }
 }
 
 pub trait ToNumber {
-    fn to_number(self) -> Result<OperandSize, ()>;
+    fn to_number(self) -> Result<OperandSize, VMError>;
 }
 
 impl ToNumber for Value {
-    fn to_number(self) -> Result<OperandSize, ()> {
+    fn to_number(self) -> Result<OperandSize, VMError> {
         // we want to consume it as well bc we're turning it to a number so no &self but self
         match self {
             Value::Number(n) => Ok(n),
-            _ => Err(()),
+            Value::Int(n) => Ok(n as OperandSize),
+            Value::BigInt(n) => Ok(n.to_f64()),
+            Value::Rational(n) => Ok(n.to_f64()),
+            value => Err(VMError::TypeMismatch { expected: "number", found: value.type_name() }),
         }
     }
 }
 
+// a bigint that fits in an int becomes one, so there's only ever one way to hold a value
+impl From<BigInt> for Value {
+    fn from(n: BigInt) -> Value {
+        match n.to_i64() {
+            Some(n) => Value::Int(n),
+            None => Value::BigInt(Shared::new(n)),
+        }
+    }
+}
+
+// an operand as an address, rather than letting `as usize` quietly turn -1 or 2.5 into something else
+pub fn to_address(operand: OperandSize) -> Result<usize, VMError> {
+    // usize::MAX as f64 rounds up to 2^64, which doesn't fit
+    if operand.fract() != 0.0 || !operand.is_finite() || operand < 0.0 || operand >= usize::MAX as OperandSize {
+        return Err(VMError::InvalidAddress(operand));
+    }
+
+    Ok(operand as usize)
+}
+
+// a number as an int, as long as it's whole and in range
+pub fn number_to_int(n: OperandSize) -> Result<i64, VMError> {
+    // i64::MAX as f64 rounds up to 2^63, which doesn't fit
+    if n.fract() != 0.0 || !n.is_finite() || n < i64::MIN as OperandSize || n >= i64::MAX as OperandSize {
+        return Err(VMError::InvalidInteger(n));
+    }
+
+    Ok(n as i64)
+}
+
+// an active TRY block
+#[derive(Clone, Copy, Debug)]
+pub struct Handler {
+    pub address: usize, // where to jump to when something is thrown
+    pub stack_depth: usize, // how deep the stack was at TRY, so we can unwind back to it
+}
+
+// why run or resume handed control back to the host
+#[derive(Clone, Debug)]
+pub enum RunState {
+    Halted, // HLT, or ran off the end of the code
+    Yielded(Value), // YIELD outside of a coroutine, resume carries on with the value it's given
+    NeedsInput, // IN (or a host instruction) is waiting on a value, resume supplies it
+    BudgetExhausted, // ran as many instructions as the budget allows, resume carries on
+    Error(VMError), // an error that nothing in the program caught
+}
+
+// set by an instruction that wants the vm to hand control back to the host
+#[derive(Clone, Debug)]
+enum Suspension {
+    Yield(Value),
+    Input,
+}
+
 pub struct VM {
     instruction_set: Vec<Instruction>,
-    code: Vec<RawInstruction>,
-    const_pool: ConstPool,
+    program: Program, // what's being run, never changed by running it
+    execution: Execution, // everything a run changes
+    budget: Option<u64>, // instructions allowed per run/resume
+    overflow_mode: OverflowMode, // what ADD, SUB, MUL and DIV do when an int result doesn't fit
+    profile: Option<Profile>, // only Some when profiling is enabled
+    tracer: Option<Box<dyn Tracer>>, // only Some when tracing is enabled
+    #[cfg(feature = "sync")]
+    actor: Option<ActorHandle>, // only Some when running inside an ActorRuntime
+}
+
+// the state of one run of a program, starting over is just making a new one
+pub struct Execution {
+    const_pool: ConstPool, // the program's consts plus whatever STOREC has stored
     stack: Stack<Value>,
+    handlers: Vec<Handler>,
+    coroutines: Vec<Shared<Lock<Coroutine>>>, // coroutines currently being resumed, innermost last
     pc: usize,
+    address: usize, // address of the instruction being (or last) executed
     stop: bool,
+    halted: bool, // stopped for good, rather than suspended
+    error: Option<VMError>, // what it stopped with, if it was an uncaught error
+    suspension: Option<Suspension>,
+    awaiting_input: bool, // whether resume should push its input
+    branched: bool, // whether the last instruction branched
 }
 
-impl VM {
-    pub fn new(instruction_set: Vec<Instruction>, initial_code : Option<Vec<RawInstruction>>, initial_consts : Option<ConstPool>,stack_size: Option<usize>  ) -> VM {
-        let local_initial_code : Vec<RawInstruction>;
-        let local_stack_size : usize;
-        let local_initial_consts: ConstPool;
-
-        match initial_code {
-            Some(raw) => local_initial_code = raw,
-            None => local_initial_code = vec![]
+impl Execution {
+    pub fn new(program: &Program, stack_size: usize) -> Execution {
+        Execution {
+            const_pool: ConstPool::new(program.consts().clone()),
+            stack: Stack::new(stack_size),
+            handlers: Vec::new(),
+            coroutines: Vec::new(),
+            pc: 0,
+            address: 0,
+            stop: false,
+            halted: false,
+            error: None,
+            suspension: None,
+            awaiting_input: false,
+            branched: false,
         }
+    }
 
-        match initial_consts {
-            Some(consts) => local_initial_consts = consts,
-            None => local_initial_consts = HashMap::new(),
-        }
+    // where the next instruction will be fetched from
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
 
-        match stack_size {
-            Some(size) => local_stack_size = size,
-            None => local_stack_size = DEFAULT_STACK_SIZE
-        }
+    // the operand stack, bottom first
+    pub fn stack(&self) -> &[Value] {
+        self.stack.as_slice()
+    }
 
-        VM{instruction_set, code: local_initial_code, stack: Stack::new(local_stack_size), const_pool: local_initial_consts, pc: 0, stop: false }
+    // whether the run has finished, either by halting or with an uncaught error
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
-    // finds the matching instruction struct for the opcode
-    pub fn get_instruction_match_for_opcode(&self, opcode: OpcodeSize) -> Option<&Instruction> {
-        for instruction in self.instruction_set.iter() {
-            if instruction.opcode == opcode {
-                return Some(instruction);
-            }
+    // how a run that's halted ended, Halted or the error that nothing caught
+    fn finished(&self) -> RunState {
+        match &self.error {
+            Some(error) => RunState::Error(error.clone()),
+            None => RunState::Halted,
         }
+    }
+}
+
+impl VM {
+    pub fn new(instruction_set: Vec<Instruction>, initial_code : Option<Vec<RawInstruction>>, initial_consts : Option<Vec<Value>>,stack_size: Option<usize>  ) -> VM {
+        let local_initial_code : Vec<RawInstruction> = initial_code.unwrap_or_default();
+        let local_initial_consts: Vec<Value> = initial_consts.unwrap_or_default();
+        let local_stack_size : usize = stack_size.unwrap_or(DEFAULT_STACK_SIZE);
 
-        None
+        VM::with_program(instruction_set, Program::new(local_initial_code, local_initial_consts), Some(local_stack_size))
     }
 
-    // loads bytecode into the code memory of the vm
-    pub fn load_bytecode(&mut self, bytecode: &mut [u8]) {
-        let mut bytecode = bytecode;
+    // a vm ready to run an already decoded program, which can be shared with any number of other vms
+    pub fn with_program(instruction_set: Vec<Instruction>, program: Program, stack_size: Option<usize>) -> VM {
+        let execution = Execution::new(&program, stack_size.unwrap_or(DEFAULT_STACK_SIZE));
+
+        VM {
+            instruction_set,
+            program,
+            execution,
+            budget: None,
+            overflow_mode: OverflowMode::default(),
+            profile: None,
+            tracer: None,
+            #[cfg(feature = "sync")]
+            actor: None,
+        }
+    }
 
-        // check the signature at the top
-        // so get the length in bytes of the signature
-        let signature_bytes_size = size_of_val(BYTECODE_SIGNATURE);
+    // finds the matching instruction struct for the opcode
+    pub fn get_instruction_match_for_opcode(&self, opcode: OpcodeSize) -> Option<&Instruction> {
+        self.instruction_set.iter().find(|instruction| instruction.opcode == opcode)
+    }
 
-        // and then we want to basically get all bytes that would encompass that in the bytecode
-        let raw_signature_bytes_from_bc: &[u8] = &bytecode[0..signature_bytes_size];
-        let signature_bytes_as_str = str::from_utf8(raw_signature_bytes_from_bc).unwrap();
+    // loads bytecode into the code memory of the vm, leaving the vm untouched if it's malformed
+    pub fn load_bytecode(&mut self, bytecode: &mut [u8]) -> Result<(), BytecodeError> {
+        let program = Program::decode(bytecode, &self.instruction_set)?;
+        self.load_program(&program);
+        Ok(())
+    }
 
-        if signature_bytes_as_str != BYTECODE_SIGNATURE {
-            panic!("unsupported bytecode signature: {}", signature_bytes_as_str);
-        }
+    // loads bytecode as it's read, e.g. from a file or a socket, without holding all of it in memory first
+    pub fn load_from_reader(&mut self, source: impl Read) -> Result<(), BytecodeError> {
+        let program = Program::from_reader(source, &self.instruction_set)?;
+        self.load_program(&program);
+        Ok(())
+    }
 
-        bytecode = &mut bytecode[signature_bytes_size..]; // basically truncate it or wtv
+    // loads an already decoded program into the vm, its constants are keyed from 0
+    // an object with imports can't be loaded until it's linked
+    pub fn load_object(&mut self, object: Object) -> Result<(), BytecodeError> {
+        let program = Program::from_object(object)?;
+        self.load_program(&program);
+        Ok(())
+    }
 
-        // and now we want to probably read constants, which has its own signature so check signature is there
-        let const_signature_bytes_size = size_of_val(BYTECODE_CONSTS_SIGNATURE);
-        let const_signature_bytes_from_bc: &[u8] = &bytecode[0..signature_bytes_size];
-        let const_signature_bytes_as_str = str::from_utf8(const_signature_bytes_from_bc).unwrap();
+    // loads a program into the vm after anything already loaded, its constants are keyed from 0
+    // into an empty vm this just shares the program's code rather than copying it
+    pub fn load_program(&mut self, program: &Program) {
+        self.program.append(program);
+        self.execution.const_pool.set_loaded(self.program.consts().clone());
+    }
 
-        let mut got_instruction_signature = false;
-        let mut cursor = 0;
+    // the program being run
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
 
-        if const_signature_bytes_as_str == BYTECODE_CONSTS_SIGNATURE {
-            bytecode = &mut bytecode[const_signature_bytes_size-1..]; // basically truncate it or wtv
-            // assume constants exist bc we got a signature
-            /*
-            constant follows this pattern:
-            type - data
-            and therefore is this many bytes:
-            1 byte - bytes based off of data type
-             */
+    // the state of the current run
+    pub fn execution(&self) -> &Execution {
+        &self.execution
+    }
 
-            // we kinda need a 'cursor' approach with this one
-            // so every type byte where there's no match, check for instructions signature
-            let mut const_count = 0;
+    // throws away the current run so the next one starts from the top, with the program's consts and an empty stack
+    // the program, budget, tracer and profiling all stay as they are
+    pub fn reset(&mut self) {
+        self.execution = Execution::new(&self.program, self.execution.stack.size());
+    }
 
-            loop {
-                if cursor >= bytecode.len() - 1 {
-                    break;
-                }
+    // the debug info of the loaded code, if it came with any
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.program.debug()
+    }
 
-                let token = bytecode[cursor];
+    // the address of the instruction being executed, or the last one executed once run or resume has returned
+    // after RunState::Error it's where the error came from
+    pub fn last_address(&self) -> usize {
+        self.execution.address
+    }
 
-                match token {
-                    1 => {
-                        // int
-                        // is OperandSize bytes
-                        let int_bytes = &bytecode[cursor..size_of::<OperandSize>()];
-                        self.const_pool.insert(const_count, Number(OperandSize::from_le_bytes(int_bytes.try_into().unwrap())));
-                        cursor += size_of::<OperandSize>() + 1;
-                        const_count += 1;
-                    }
-                    2 => {
-                        // string
-                        // has a dynamic amount of bytes
-                        // string length is u32
-                        let string_length_in_bytes = &bytecode[cursor + 1.. cursor + 1 + size_of::<u32>()];
-                        let string_length = u32::from_le_bytes(string_length_in_bytes.try_into().unwrap());
-                        let string_bytes = &bytecode[cursor + 1 + size_of::<u32>()..cursor + 1 + size_of::<u32>() + string_length as usize];
-                        let string = str::from_utf8(&string_bytes);
-
-                        if string.is_ok() {
-                            self.const_pool.insert(const_count, Str(Rc::new(string.unwrap().to_string())));
-                            cursor += 1 + size_of::<u32>() + string_length as usize;
-                            const_count += 1;
-                        } else {
-                            panic!("couldn't decode string from {:?} with provided length {}", string_bytes, string_length);
-                        }
-                    }
-                    3 => {
-                        // boolean
-                        // is 1 byte
-                        let boolean_byte = bytecode[cursor + 1];
-                        self.const_pool.insert(const_count, Value::Bool(boolean_byte != 0));
-                        cursor += 2;
-                        const_count += 1;
-                    }
-                    4 => {
-                        // nil
-                        // is 0 bytes
-                        self.const_pool.insert(const_count, Value::Nil);
-                        cursor += 1;
-                        const_count += 1;
-                    }
-                    _ => {
-                        // not specified so we check for instructions signature here
-                        // also let's increment the cursor
-                        let instr_signature_bytes_size = size_of_val(BYTECODE_INSTRUCTIONS_SIGNATURE);
-                        let instr_signature_bytes_from_bc: &[u8] = &bytecode[cursor..instr_signature_bytes_size];
-                        let instr_signature_bytes_as_str = str::from_utf8(instr_signature_bytes_from_bc).unwrap();
-
-                        if instr_signature_bytes_as_str == BYTECODE_INSTRUCTIONS_SIGNATURE {
-                            got_instruction_signature = true;
-                            cursor += instr_signature_bytes_size;
-                        }
-                    }
-                }
-            }
+    // the address along with its source location and symbol, if there's debug info
+    pub fn describe_address(&self, address: usize) -> String {
+        match self.program.debug() {
+            Some(debug) => debug.describe(address),
+            None => address.to_string(),
         }
+    }
 
-        bytecode = &mut bytecode[cursor..];
-
-        // and now we probably want to check there's an instructions signature
-        if !got_instruction_signature {
-            let instr_signature_bytes_size = size_of_val(BYTECODE_INSTRUCTIONS_SIGNATURE);
-            let instr_signature_bytes_from_bc: &[u8] = &bytecode[cursor..instr_signature_bytes_size];
-            let instr_signature_bytes_as_str = str::from_utf8(instr_signature_bytes_from_bc).unwrap();
 
-            if instr_signature_bytes_as_str == BYTECODE_INSTRUCTIONS_SIGNATURE {
-                bytecode = &mut bytecode[instr_signature_bytes_size-1..];
-                got_instruction_signature = true;
-            } else {
-                return;
-            }
+    // runs the vm until it halts, suspends, runs out of budget or an error goes uncaught
+    // once it has halted or errored it stays that way, giving back the same state, until reset starts a new run
+    pub fn run(&mut self) -> RunState {
+        if self.execution.halted {
+            return self.execution.finished();
         }
 
-        // below is code prior to the constant table, so to leave it untouched we reassign what bytecode is to not include constants
-        // every virtual register is technically OpcodeSize + OperandSize
-        // so we go through the vector of bytes in increments of (OpcodeSize + OperandSize)
-        let opcode_bytes_size = size_of::<OpcodeSize>();
-        let operand_bytes_size = size_of::<OperandSize>();
+        self.execution.awaiting_input = false;
 
-        let raw_instruction_size = size_of::<OpcodeSize>() + size_of::<OperandSize>();
+        self.execute()
+    }
 
-        let mut raw_instructions_vec = Vec::with_capacity(bytecode.len() / raw_instruction_size);
+    // carries on from exactly where run or resume last stopped
+    // after Yielded or NeedsInput the input (or nil if there isn't one) is pushed first, as the result of that instruction
+    pub fn resume(&mut self, input: Option<Value>) -> RunState {
+        if self.execution.halted {
+            return self.execution.finished();
+        }
 
-        let mut local_opcode: OpcodeSize = 0;
-        let mut local_operand: OperandSize;
-        let mut bytes_encountered = Vec::with_capacity(raw_instruction_size);
-        let mut opcode_encountered = false;
+        if self.execution.awaiting_input {
+            self.execution.awaiting_input = false;
 
-        for byte in bytecode {
-            if opcode_encountered {
-                if bytes_encountered.len() == operand_bytes_size {
-                    // we've got the operand now!
-                    let raw_operand = bytes_encountered.clone();
-                    local_operand = OperandSize::from_le_bytes(raw_operand.try_into().unwrap());
+            let pushed = self.push(input.unwrap_or(Value::Nil));
+            if let Err(error) = pushed.or_else(|error| self.raise(error)) {
+                return self.fail(error);
+            }
+        }
 
-                    // reset bytes encountered
-                    bytes_encountered = Vec::with_capacity(operand_bytes_size);
+        self.execute()
+    }
 
-                    // now form our instruction and push!
-                    raw_instructions_vec.push(RawInstruction{opcode: local_opcode, operand: Some(local_operand)});
+    // limits how many instructions each run or resume executes before handing back BudgetExhausted
+    pub fn set_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+    }
 
-                    opcode_encountered = false;
-                }
-            } else {
-                if bytes_encountered.len() == opcode_bytes_size {
-                    opcode_encountered = true;
-                    let raw_opcode = bytes_encountered.clone();
-                    local_opcode = OpcodeSize::from_le_bytes(raw_opcode.try_into().unwrap());
-
-                    // reset bytes encountered
-                    bytes_encountered = Vec::with_capacity(raw_instruction_size);
-
-                    // we've encountered our opcode, but let's check we also need operand
-                    // this does mean searching through our set of instructions
-                    let instruction = self.get_instruction_match_for_opcode(local_opcode);
-                    match instruction {
-                        Some(instruction) => {
-                            if !instruction.requires_operand {
-                                // finalise instruction and push since it doesn't require operand
-                                raw_instructions_vec.push(RawInstruction{opcode : local_opcode, operand : None});
-                                // back to searching for more opcodes!
-                                opcode_encountered = false;
-                            }
-                        }
-                        None => {
-                            // no matching instruction </3
-                            panic!("illegal instruction");
-                        }
-                    }
-                }
+    // what the plain arithmetic instructions do when an int result doesn't fit, checked (an error) unless it's changed
+    pub fn set_overflow_mode(&mut self, mode: OverflowMode) {
+        self.overflow_mode = mode;
+    }
 
-            }
+    pub fn overflow_mode(&self) -> OverflowMode {
+        self.overflow_mode
+    }
 
-            bytes_encountered.push(*byte);
+    fn execute(&mut self) -> RunState {
+        match self.execute_until_stopped() {
+            Ok(state) => state,
+            Err(error) => self.fail(error),
         }
+    }
 
-        self.code.append(&mut raw_instructions_vec);
+    // stops the run for good with an error nothing caught
+    fn fail(&mut self, error: VMError) -> RunState {
+        self.execution.halted = true;
+        self.execution.error = Some(error.clone());
+        RunState::Error(error)
     }
 
+    fn execute_until_stopped(&mut self) -> Result<RunState, VMError> {
+        self.execution.stop = false;
 
-    // runs the vm
-    pub fn run(&mut self) {
-        self.stop = false;
+        let budget = self.budget.unwrap_or(u64::MAX);
+        let mut executed = 0;
 
         loop {
-            if self.stop == true {
-                break;
+            if self.execution.stop {
+                // an instruction either halted the vm or wants the host to step in
+                return Ok(match self.execution.suspension.take() {
+                    Some(Suspension::Yield(value)) => {
+                        self.execution.awaiting_input = true;
+                        RunState::Yielded(value)
+                    }
+                    Some(Suspension::Input) => {
+                        self.execution.awaiting_input = true;
+                        RunState::NeedsInput
+                    }
+                    None => {
+                        self.execution.halted = true;
+                        RunState::Halted
+                    }
+                });
+            }
+
+            if executed == budget {
+                return Ok(RunState::BudgetExhausted);
             }
+            executed += 1;
 
-            self.pc += 1;
+            let current_address = self.execution.pc;
+            self.execution.address = current_address;
 
-            let current_address = self.pc - 1; //  so a branch doesnt need to do (addr - 1)
+            // moved on before running it so a branch doesnt need to do (addr - 1), which there's no room for past usize::MAX
+            self.execution.pc = current_address.checked_add(1).ok_or(VMError::InvalidAddress(current_address as OperandSize))?;
 
             // and a check to make sure we don't go out of limits
-            if current_address >= self.code.len() {
-                self.stop = true;
-                break;
+            if current_address >= self.program.code().len() {
+                // running off the end of a coroutine just finishes it
+                if self.in_coroutine() {
+                    self.finish_coroutine()?;
+                    continue;
+                }
+
+                self.execution.stop = true;
+                continue;
             }
 
-            let current_raw_instruction = &self.code[current_address];
+            let current_raw_instruction = &self.program.code()[current_address];
 
             let opcode = current_raw_instruction.opcode;
             let operand = current_raw_instruction.operand;
@@ -292,79 +544,683 @@ impl VM {
             let matching_instruction = self.get_instruction_match_for_opcode(opcode);
 
             if matching_instruction.is_none() {
-                self.stop = true;
-                break;
+                // treated like any other error so a handler still gets a chance at it
+                self.raise(VMError::IllegalInstruction(opcode))?;
+                continue;
             }
 
+            let instruction = matching_instruction.unwrap();
+            let func = instruction.func;
+            let name = instruction.name;
+
+            // only bother timing when we're profiling
+            let started = self.profile.as_ref().map(|_| Instant::now());
+            self.execution.branched = false;
+
+            // and only copy the stack when a tracer wants this instruction
+            let stack_before = match &self.tracer {
+                Some(tracer) if tracer.wants(current_address, opcode) => Some(self.execution.stack.as_slice().to_vec()),
+                _ => None,
+            };
+
             // bc all instructions will always provide an operand as a number
             // and because we rely on the stack -> so much easier
-            match operand {
+            let result = match operand {
                 Some(operand) => {
-                    (matching_instruction.unwrap().func)(self, Some(Value::Number(operand)));
+                    func(self, Some(Value::Number(operand)))
                 }
                 None => {
-                    (matching_instruction.unwrap().func)(self, None);
+                    func(self, None)
                 }
+            };
+
+            if let (Some(profile), Some(started)) = (self.profile.as_mut(), started) {
+                profile.record(current_address, opcode, name, started.elapsed(), self.execution.branched, self.execution.stack.len());
+            }
+
+            if let (Some(tracer), Some(stack_before)) = (self.tracer.as_mut(), stack_before) {
+                tracer.trace(&TraceEvent {
+                    address: current_address,
+                    opcode,
+                    name,
+                    operand,
+                    location: self.program.debug().and_then(|debug| debug.location(current_address)),
+                    stack_before: &stack_before,
+                    stack_after: self.execution.stack.as_slice(),
+                });
+            }
+
+            if let Err(error) = result {
+                self.raise(error)?;
             }
         }
     }
 
+    // hands the value to the host, run or resume gives back RunState::Yielded
+    pub fn yield_to_host(&mut self, value: Value) {
+        self.execution.suspension = Some(Suspension::Yield(value));
+        self.execution.stop = true;
+    }
+
+    // asks the host for a value, run or resume gives back RunState::NeedsInput
+    pub fn request_input(&mut self) {
+        self.execution.suspension = Some(Suspension::Input);
+        self.execution.stop = true;
+    }
+
+    // hands the error over to the innermost handler, unwinding the stack back to how it was at TRY
+    // if a coroutine has no handler it dies and the error carries on in whoever resumed it
+    // if there's no handler at all the vm stops and the error is given back
+    fn raise(&mut self, error: VMError) -> Result<(), VMError> {
+        loop {
+            if let Some(handler) = self.execution.handlers.pop() {
+                self.execution.stack.truncate(handler.stack_depth);
+                self.push(error.into_value())?;
+                self.execution.pc = handler.address;
+                self.execution.branched = true;
+                return Ok(());
+            }
+
+            if !self.in_coroutine() {
+                self.execution.stop = true;
+                return Err(error);
+            }
+
+            self.leave_coroutine(CoroutineStatus::Dead)?;
+        }
+    }
+
+    // starts a TRY block, anything thrown until the matching ENDTRY jumps to the handler address
+    pub fn push_handler(&mut self, address: OperandSize) -> Result<(), VMError> {
+        self.execution.handlers.push(Handler { address: to_address(address)?, stack_depth: self.execution.stack.len() });
+        Ok(())
+    }
+
+    // ends the innermost TRY block
+    pub fn pop_handler(&mut self) -> Result<Handler, VMError> {
+        self.execution.handlers.pop().ok_or(VMError::NoHandler)
+    }
+
+    // sets the tracer invoked for every executed instruction, None turns tracing off
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) {
+        self.tracer = tracer;
+    }
+
+    // removes the tracer and hands it back
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        self.tracer.take()
+    }
+
+    // connects the vm to an actor runtime's mailboxes
+    #[cfg(feature = "sync")]
+    pub fn set_actor(&mut self, actor: Option<ActorHandle>) {
+        self.actor = actor;
+    }
+
+    #[cfg(feature = "sync")]
+    pub fn actor(&self) -> Option<&ActorHandle> {
+        self.actor.as_ref()
+    }
+
+    // turns on profiling for subsequent runs, discarding anything previously recorded
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::new(self.program.code().len()));
+    }
+
+    // turns off profiling and hands back what was recorded
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    // gets what has been recorded so far, if profiling is enabled
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    // creates a suspended coroutine that starts at the supplied virtual address
+    pub fn create_coroutine(&mut self, address: OperandSize) -> Result<Value, VMError> {
+        Ok(Value::Coroutine(Shared::new(Lock::new(Coroutine::new(to_address(address)?, self.execution.stack.size())))))
+    }
+
+    // switches over to the coroutine, handing it the value
+    // on its first resume the value is the only thing on its stack, after that it's what YIELD gives back
+    pub fn resume_coroutine(&mut self, coroutine: Shared<Lock<Coroutine>>, value: Value) -> Result<(), VMError> {
+        {
+            let mut co = coroutine.borrow_mut();
+
+            if co.status != CoroutineStatus::Suspended {
+                return Err(VMError::CannotResume(co.status));
+            }
+
+            co.status = CoroutineStatus::Running;
+            self.swap_coroutine_state(&mut co);
+        }
+
+        if let Some(resumer) = self.execution.coroutines.last() {
+            resumer.borrow_mut().status = CoroutineStatus::Normal;
+        }
+
+        self.execution.coroutines.push(coroutine);
+        self.push(value)
+    }
+
+    // suspends the running coroutine, handing the value back to whoever resumed it
+    pub fn yield_coroutine(&mut self, value: Value) -> Result<(), VMError> {
+        self.leave_coroutine(CoroutineStatus::Suspended)?;
+        self.push(value)
+    }
+
+    // ends the running coroutine, handing whatever's on top of its stack back to whoever resumed it
+    pub fn finish_coroutine(&mut self) -> Result<(), VMError> {
+        let value = self.pop().unwrap_or(Value::Nil);
+        self.leave_coroutine(CoroutineStatus::Dead)?;
+        self.push(value)
+    }
+
+    // whether the code running right now belongs to a coroutine
+    pub fn in_coroutine(&self) -> bool {
+        !self.execution.coroutines.is_empty()
+    }
+
+    // switches from the running coroutine back to whoever resumed it
+    fn leave_coroutine(&mut self, status: CoroutineStatus) -> Result<(), VMError> {
+        let coroutine = self.execution.coroutines.pop().ok_or(VMError::NotInCoroutine)?;
+
+        {
+            let mut co = coroutine.borrow_mut();
+            self.swap_coroutine_state(&mut co);
+            co.status = status;
+
+            if status == CoroutineStatus::Dead {
+                // it's never running again so there's no point holding on to its stack
+                co.stack.truncate(0);
+                co.handlers.clear();
+            }
+        }
+
+        if let Some(resumer) = self.execution.coroutines.last() {
+            resumer.borrow_mut().status = CoroutineStatus::Running;
+        }
+
+        Ok(())
+    }
+
+    // the running coroutine's state lives in the vm, so switching is just swapping it with the stored state
+    fn swap_coroutine_state(&mut self, coroutine: &mut Coroutine) {
+        mem::swap(&mut self.execution.stack, &mut coroutine.stack);
+        mem::swap(&mut self.execution.handlers, &mut coroutine.handlers);
+        mem::swap(&mut self.execution.pc, &mut coroutine.pc);
+    }
+
     // pops the topmost item off of the operand stack
-    pub fn pop(&mut self) -> Option<Value> {
-        self.stack.pop()
+    pub fn pop(&mut self) -> Result<Value, VMError> {
+        self.execution.stack.pop().ok_or(VMError::StackUnderflow)
     }
 
     // pushes the supplied operand onto the operand stack
-    pub fn push(&mut self, operand: Value) {
-        self.stack.push(operand);
+    pub fn push(&mut self, operand: Value) -> Result<(), VMError> {
+        self.execution.stack.push(operand).map_err(|_| VMError::StackOverflow)
     }
 
     // peeks at the top of the operand stack
     pub fn peek(&mut self) -> Option<&Value> {
-        self.stack.peek()
+        self.execution.stack.peek()
     }
 
     // branches to supplied virtual address
-    pub fn branch(&mut self, operand: OperandSize) {
-        self.pc = operand as usize
+    pub fn branch(&mut self, operand: OperandSize) -> Result<(), VMError> {
+        self.execution.pc = to_address(operand)?;
+        self.execution.branched = true;
+        Ok(())
     }
 
     // halts the vm
     pub fn halt(&mut self) {
-        self.stop = true;
+        self.execution.stop = true;
     }
 
     // dumps the contents of the code memory (bytecode) into a readable manner
+    // labels and source locations come from the debug info when there is some
+    // e.g. 0004  BRZ    loop       ; main.lsma:6:5
     pub fn dump(&self) -> String {
-        !unimplemented!()
+        let mut out = String::new();
+
+        for (address, raw_instruction) in self.program.code().iter().enumerate() {
+            if let Some(debug) = self.program.debug() {
+                for name in debug.symbols_at(address) {
+                    out.push_str(&format!("{}:\n", name));
+                }
+            }
+
+            let Some(instruction) = self.get_instruction_match_for_opcode(raw_instruction.opcode) else {
+                out.push_str(&format!("{:04}  ??? {}\n", address, raw_instruction.opcode));
+                continue;
+            };
+
+            let mut comments = Vec::new();
+
+            let operand = match (instruction.operand, raw_instruction.operand) {
+                (_, None) => String::new(),
+                (OperandKind::Address, Some(target)) => {
+                    // a label reads better than a number, if the address has one
+                    match self.program.debug().and_then(|debug| debug.symbols_at(target as usize).next()) {
+                        Some(name) => name.to_string(),
+                        None => target.to_string(),
+                    }
+                }
+                (OperandKind::ConstKey, Some(key)) => {
+                    if let Ok(value) = self.get_const_ref(key) {
+                        comments.push(value.to_string());
+                    }
+                    key.to_string()
+                }
+                (_, Some(operand)) => operand.to_string(),
+            };
+
+            if let Some(location) = self.program.debug().and_then(|debug| debug.location(address)) {
+                comments.push(location.to_string());
+            }
+
+            let line = if comments.is_empty() {
+                format!("{:04}  {:<6} {}", address, instruction.name, operand)
+            } else {
+                format!("{:04}  {:<6} {:<10} ; {}", address, instruction.name, operand, comments.join(", "))
+            };
+
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+
+        out
+    }
+
+    // gets the reference to the value a const handle points at
+    pub fn get_const_ref(&self, key: OperandSize) -> Result<&Value, VMError> {
+        let handle = ConstHandle::from_operand(key).ok_or(VMError::MissingConst(key))?;
+        self.execution.const_pool.get(handle)
+    }
+
+    // gets the copy of the value a const handle points at
+    // loaded consts are shared with every run of the program, so a list from one is copied rather than handed out to be changed
+    pub fn get_const_copy(&self, key: OperandSize) -> Result<Value, VMError> {
+        match ConstHandle::from_operand(key) {
+            Some(ConstHandle::Loaded(_)) => self.get_const_ref(key).map(Value::deep_copy),
+            _ => self.get_const_ref(key).cloned(),
+        }
+    }
+
+    // deletes a stored value, loaded consts are read-only
+    pub fn remove_const(&mut self, key: OperandSize) -> Result<Value, VMError> {
+        let handle = ConstHandle::from_operand(key).ok_or(VMError::MissingConst(key))?;
+        self.execution.const_pool.remove(handle)
+    }
+
+    // stores the value, reusing a deleted value's slot if there is one, and returns its handle
+    pub fn store_const(&mut self, value: Value) -> ConstHandle {
+        self.execution.const_pool.store(value)
+    }
+
+    // the loaded consts and stored values
+    pub fn const_pool(&self) -> &ConstPool {
+        &self.execution.const_pool
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsm::assembler::assemble;
+    use crate::lsm::bytecode::BYTECODE_VERSION_1;
+    use crate::lsm::instruction::DEFAULT_INSTRUCTION_SET;
+
+    // 2^64, the first address past usize::MAX, which `as usize` would have turned into usize::MAX
+    const PAST_USIZE: OperandSize = 18446744073709551616.0;
+
+    fn raw(opcode: OpcodeSize, operand: Option<OperandSize>) -> RawInstruction {
+        RawInstruction { opcode, operand }
+    }
+
+    fn vm(code: Vec<RawInstruction>) -> VM {
+        VM::new(DEFAULT_INSTRUCTION_SET.to_vec(), Some(code), None, None)
+    }
+
+    fn assembled(source: &str) -> VM {
+        let mut vm = vm(Vec::new());
+        vm.load_object(assemble(source, "test.lsma", DEFAULT_INSTRUCTION_SET).unwrap()).unwrap();
+        vm
+    }
+
+    // what's left on the stack, bottom first, when it's all numbers
+    fn numbers(vm: &VM) -> Vec<OperandSize> {
+        vm.execution().stack().iter().map(|value| match value {
+            Value::Number(n) => *n,
+            value => panic!("expected a number, got {:?}", value),
+        }).collect()
+    }
+
+    fn invalid_address(state: RunState) -> bool {
+        matches!(state, RunState::Error(VMError::InvalidAddress(address)) if address == PAST_USIZE)
+    }
+
+    #[test]
+    fn addresses_have_to_be_whole_and_fit_a_usize() {
+        assert_eq!(to_address(0.0).unwrap(), 0);
+        assert_eq!(to_address(42.0).unwrap(), 42);
+
+        for operand in [-1.0, 2.5, PAST_USIZE, OperandSize::NAN, OperandSize::INFINITY] {
+            assert!(matches!(to_address(operand), Err(VMError::InvalidAddress(_))), "{}", operand);
+        }
+    }
+
+    #[test]
+    fn branching_past_usize_is_an_error() {
+        let code = vec![raw(10, Some(PAST_USIZE))]; // BRA
+        assert!(invalid_address(vm(code).run()));
+    }
+
+    #[test]
+    fn a_handler_past_usize_is_an_error() {
+        let code = vec![
+            raw(15, Some(PAST_USIZE)), // TRY
+            raw(1, Some(1.0)), // PUSH
+            raw(17, None), // THROW
+        ];
+        assert!(invalid_address(vm(code).run()));
+    }
+
+    #[test]
+    fn version_1_bytecode_branching_past_usize_is_an_error() {
+        // version 1 keeps operands as f64, so it can hold an address nothing else would encode
+        let object = Object { code: vec![raw(10, Some(PAST_USIZE))], ..Object::default() };
+        let mut bytecode = object.encode_version(BYTECODE_VERSION_1, DEFAULT_INSTRUCTION_SET).unwrap();
+
+        let mut vm = vm(Vec::new());
+        vm.load_bytecode(&mut bytecode).unwrap();
+        assert!(invalid_address(vm.run()));
+    }
+
+    #[test]
+    fn a_halted_run_stays_halted_until_reset() {
+        let code = vec![
+            raw(1, Some(1.0)), // PUSH
+            raw(0, None), // HLT
+            raw(1, Some(2.0)), // PUSH
+        ];
+        let mut vm = vm(code);
+
+        assert!(matches!(vm.run(), RunState::Halted));
+        assert!(matches!(vm.run(), RunState::Halted));
+        assert!(matches!(vm.resume(None), RunState::Halted));
+        // nothing past the HLT ran
+        assert!(matches!(vm.execution().stack(), [Value::Number(n)] if *n == 1.0));
+
+        vm.reset();
+        assert!(matches!(vm.run(), RunState::Halted));
+        assert!(matches!(vm.execution().stack(), [Value::Number(n)] if *n == 1.0));
+    }
+
+    #[test]
+    fn an_errored_run_keeps_its_error_until_reset() {
+        let code = vec![
+            raw(2, None), // POP
+            raw(1, Some(1.0)), // PUSH
+        ];
+        let mut vm = vm(code);
+
+        assert!(matches!(vm.run(), RunState::Error(VMError::StackUnderflow)));
+        assert!(matches!(vm.run(), RunState::Error(VMError::StackUnderflow)));
+        assert!(matches!(vm.resume(None), RunState::Error(VMError::StackUnderflow)));
+        assert!(vm.execution().stack().is_empty());
+
+        vm.reset();
+        assert!(matches!(vm.run(), RunState::Error(VMError::StackUnderflow)));
+    }
+
+    #[test]
+    fn throw_unwinds_the_stack_back_to_the_try() {
+        let mut vm = assembled("
+    PUSH 1
+    TRY handler
+    PUSH 2
+    PUSH 3
+    PUSH 99
+    THROW
+    HLT
+handler:
+    HLT
+");
+
+        assert!(matches!(vm.run(), RunState::Halted));
+        assert_eq!(numbers(&vm), [1.0, 99.0]);
+    }
+
+    #[test]
+    fn the_innermost_handler_catches_first_and_can_rethrow() {
+        let mut vm = assembled("
+    TRY outer
+    PUSH 1
+    TRY inner
+    PUSH 2
+    PUSH 10
+    THROW
+inner:
+    PUSH 1
+    ADD
+    THROW
+outer:
+    HLT
+");
+
+        // inner unwinds to [1], adds 1 to the 10 and throws that to outer, which unwinds to nothing
+        assert!(matches!(vm.run(), RunState::Halted));
+        assert_eq!(numbers(&vm), [11.0]);
     }
 
-    // gets the reference to a value at specified key of the const pool
-    pub fn get_const_ref(&self, key: OperandSize) -> Option<&Value> {
-        self.const_pool.get(&(key as usize))
+    #[test]
+    fn vm_errors_are_caught_as_their_message() {
+        let mut vm = assembled("
+    TRY handler
+    POP
+    HLT
+handler:
+    HLT
+");
+
+        assert!(matches!(vm.run(), RunState::Halted));
+        assert!(matches!(vm.execution().stack(), [Value::Str(message)] if message.as_str() == "stack underflow"));
     }
 
-    // gets the copy of a value at specified key of the const pool
-    pub fn get_const_copy(&self, key: OperandSize) -> Option<Value> {
-        if let Some(value) = self.const_pool.get(&(key as usize)) {
-            Some(value.clone())
-        } else {
-            None
+    #[test]
+    fn endtry_drops_the_handler() {
+        let mut vm = assembled("
+    TRY handler
+    ENDTRY
+    PUSH 5
+    THROW
+handler:
+    HLT
+");
+
+        assert!(matches!(vm.run(), RunState::Error(VMError::Thrown(Value::Number(n))) if n == 5.0));
+
+        let mut vm = assembled("ENDTRY");
+        assert!(matches!(vm.run(), RunState::Error(VMError::NoHandler)));
+    }
+
+    #[test]
+    fn coroutines_pass_values_through_yield_and_resume() {
+        // the main program hands the host each thing it wants checked with a YIELD of its own
+        let mut vm = assembled("
+    CREATE_CO co
+    DUP
+    COSTATUS
+    YIELD
+    POP
+    DUP
+    PUSH 10
+    RESUME
+    YIELD
+    POP
+    DUP
+    COSTATUS
+    YIELD
+    POP
+    DUP
+    PUSH 5
+    RESUME
+    YIELD
+    POP
+    DUP
+    COSTATUS
+    YIELD
+    POP
+    PUSH 0
+    RESUME
+    HLT
+co:
+    PUSH 2
+    MUL
+    YIELD
+    PUSH 1
+    ADD
+");
+
+        // suspended, 10 doubled, suspended again, 5 plus 1 as it runs off the end, then dead
+        let mut state = vm.run();
+        for expected in [1.0, 20.0, 1.0, 6.0, 0.0] {
+            assert!(matches!(state, RunState::Yielded(Value::Number(n)) if n == expected), "{}", expected);
+            state = vm.resume(None);
         }
+
+        assert!(matches!(state, RunState::Error(VMError::CannotResume(CoroutineStatus::Dead))));
     }
 
-    // removes the value at specified key of the const pool
-    pub fn remove_const(&mut self, key: OperandSize) {
-        self.const_pool.remove(&(key as usize));
+    #[test]
+    fn a_coroutine_is_running_while_it_runs() {
+        // it's handed itself to look at
+        let mut vm = assembled("
+    CREATE_CO co
+    DUP
+    DUP
+    RESUME
+    HLT
+co:
+    COSTATUS
+    YIELD
+");
+
+        assert!(matches!(vm.run(), RunState::Halted));
+        assert!(matches!(vm.execution().stack(), [Value::Coroutine(_), Value::Number(n)] if *n == 2.0));
+        assert!(!vm.in_coroutine());
     }
 
-    // stores the const provided and returns key value
-    pub fn store_const(&mut self, value: Value) -> usize {
-        // key should be length of hashmap + 1 (basically a counter)
-        let key = self.const_pool.len() + 1usize;
+    #[test]
+    fn an_uncaught_error_kills_the_coroutine_and_carries_on_in_the_resumer() {
+        let mut vm = assembled("
+    CREATE_CO co
+    TRY caught
+    DUP
+    PUSH 1
+    RESUME
+    HLT
+caught:
+    YIELD
+    POP
+    COSTATUS
+    HLT
+co:
+    PUSH 3
+    PUSH 7
+    THROW
+");
+
+        // the resumer's stack is back to how it was at its TRY, with just the coroutine under what was thrown
+        assert!(matches!(vm.run(), RunState::Yielded(Value::Number(n)) if n == 7.0));
+        assert!(matches!(vm.resume(None), RunState::Halted));
+        assert_eq!(numbers(&vm), [0.0]);
+    }
 
-        self.const_pool.insert(key, value);
+    #[test]
+    fn an_error_nothing_catches_ends_the_run_from_inside_a_coroutine() {
+        let mut vm = assembled("
+    CREATE_CO co
+    PUSH 1
+    RESUME
+    HLT
+co:
+    PUSH 7
+    THROW
+");
+
+        assert!(matches!(vm.run(), RunState::Error(VMError::Thrown(Value::Number(n))) if n == 7.0));
+        assert!(!vm.in_coroutine());
+    }
+
+    #[test]
+    fn resume_hands_a_yield_its_result() {
+        let mut vm = assembled("
+    PUSH 1
+    YIELD
+    PUSH 1
+    ADD
+    YIELD
+    HLT
+");
+
+        assert!(matches!(vm.run(), RunState::Yielded(Value::Number(n)) if n == 1.0));
+        assert!(matches!(vm.resume(Some(Value::Number(41.0))), RunState::Yielded(Value::Number(n)) if n == 42.0));
+
+        // nothing given is nil
+        assert!(matches!(vm.resume(None), RunState::Halted));
+        assert!(matches!(vm.execution().stack(), [Value::Nil]));
+    }
+
+    #[test]
+    fn resume_gives_in_its_input() {
+        let mut vm = assembled("
+    IN
+    PUSH 2
+    MUL
+    HLT
+");
+
+        assert!(matches!(vm.run(), RunState::NeedsInput));
+        assert!(matches!(vm.resume(Some(Value::Number(21.0))), RunState::Halted));
+        assert_eq!(numbers(&vm), [42.0]);
+    }
+
+    #[test]
+    fn a_budget_pauses_the_run_without_taking_input() {
+        let mut vm = assembled("
+    PUSH 1
+    PUSH 2
+    ADD
+    PUSH 3
+    HLT
+");
+        vm.set_budget(Some(2));
+
+        assert!(matches!(vm.run(), RunState::BudgetExhausted));
+        assert_eq!(numbers(&vm), [1.0, 2.0]);
+
+        // it wasn't waiting on anything, so the input's ignored
+        assert!(matches!(vm.resume(Some(Value::Number(99.0))), RunState::BudgetExhausted));
+        assert_eq!(numbers(&vm), [3.0, 3.0]);
+
+        assert!(matches!(vm.resume(None), RunState::Halted));
+        assert_eq!(numbers(&vm), [3.0, 3.0]);
+    }
 
-        key
+    #[test]
+    fn run_after_a_yield_carries_on_without_pushing_anything() {
+        let mut vm = assembled("
+    PUSH 5
+    YIELD
+    PUSH 6
+    HLT
+");
+
+        assert!(matches!(vm.run(), RunState::Yielded(Value::Number(n)) if n == 5.0));
+        assert!(matches!(vm.run(), RunState::Halted));
+        assert_eq!(numbers(&vm), [6.0]);
     }
-}
\ No newline at end of file
+}
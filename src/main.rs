@@ -1,45 +1,479 @@
-mod lsm;
-
+#[cfg(feature = "sync")]
+use std::collections::HashMap;
 use std::env;
-use crate::lsm::{DEFAULT_INSTRUCTION_SET, VM};
+use std::fs;
+use std::io;
+use std::path::Path;
+#[cfg(feature = "sync")]
+use little_stack_machine::lsm::{ActorRuntime, Program, ACTOR_INSTRUCTION_SET};
+use little_stack_machine::lsm::{assemble, section_name, Checksum, FLAG_CHECKSUMS, FLAG_NEEDS_LINKING, BYTECODE_VERSION_1, LATEST_BYTECODE_VERSION, link, intern_string, FilteredTracer, OverflowMode, Instruction, JsonTracer, Object, RunState, TextTracer, TraceFilter, Tracer, VMError, Value, DEFAULT_INSTRUCTION_SET, MATH_INSTRUCTION_SET, VM};
+
+const USAGE: &str = "usage: lsm --file <bytecode file>/--string <string> [--overflow wrapping|checked|saturating|promote]
+       lsm asm <source file> [-o <object file>] [--bytecode-version <version>]
+       lsm link <object file>... -o <bytecode file> [--bytecode-version <version>]
+       lsm disasm <bytecode or object file>
+       lsm inspect <bytecode or object file>
+       lsm convert <bytecode or object file> -o <output file> [--bytecode-version <version>]
+       lsm profile <bytecode file> [--json]
+       lsm trace <bytecode file> [--json] [--from <address>] [--to <address>] [--opcode <name>[,<name>...]]
+       lsm actors <bytecode file>... (needs the sync feature)";
 
 fn main() {
-    let args: Vec<String> = env::args().skip(1).collect();
-    dbg!(&args);
+    let (args, overflow_mode) = split_overflow_mode(env::args().skip(1).collect());
 
-    if args.len() < 3 {
-        eprintln!("usage: lsm --file <source file>/--string <string> ");
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
         std::process::exit(1);
     }
 
-    let instruction_set = DEFAULT_INSTRUCTION_SET.to_vec();
-    let mut vm = VM::new(instruction_set, None, None, None);
+    let instruction_set = instruction_set();
+    let mut vm = VM::new(instruction_set.clone(), None, None, None);
+    vm.set_overflow_mode(overflow_mode);
 
-    // check if it's --file or --string
-    match args[1].as_str() {
+    // check if it's --file, --string or a subcommand
+    match args[0].as_str() {
         "--file" => {
-            // get entirety of file contents
+            // the bytecode's loaded as it's read rather than all at once, - reads it from stdin
+            let loaded = match args[1].as_str() {
+                "-" => vm.load_from_reader(io::stdin().lock()),
+                path => match fs::File::open(path) {
+                    Ok(file) => vm.load_from_reader(file),
+                    Err(err) => {
+                        eprintln!("couldn't read {}: {}", path, err);
+                        std::process::exit(1);
+                    }
+                },
+            };
+
+            if let Err(err) = loaded {
+                eprintln!("couldn't load {}: {}", args[1], err);
+                std::process::exit(1);
+            }
 
-            // send the contents into the vm
+            let result = drive(&mut vm);
+            exit_on_error(&vm, result);
         }
 
         "--string" => {
             // we just send the input after --string into the vm
-            let string = &args[2..];
-            let mut bytes : &mut [u8] = &mut [];
+            let string = &args[1..];
+            let mut bytes: Vec<u8> = Vec::new();
 
             for s in string {
-                let s_as_bytes = s.as_bytes();
-                bytes.copy_from_slice(s_as_bytes);
+                bytes.extend_from_slice(s.as_bytes());
+            }
+
+            load(&mut vm, "the string", &mut bytes);
+
+            let result = drive(&mut vm);
+            exit_on_error(&vm, result);
+        }
+
+        "asm" => {
+            let (inputs, output, version) = split_output(&args[1..]);
+            let [input] = inputs.as_slice() else {
+                eprintln!("{}", USAGE);
+                std::process::exit(1);
+            };
+
+            let source = String::from_utf8_lossy(&read_file(input)).into_owned();
+            let output = output.unwrap_or_else(|| Path::new(input).with_extension("lsmo").to_string_lossy().into_owned());
+
+            match assemble(&source, input, &instruction_set) {
+                Ok(object) => write_file(&output, &encode(&object, version, &instruction_set)),
+                Err(err) => {
+                    eprintln!("{}: {}", input, err);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        "link" => {
+            let (inputs, output, version) = split_output(&args[1..]);
+            let Some(output) = output else {
+                eprintln!("{}", USAGE);
+                std::process::exit(1);
+            };
+
+            let objects: Vec<Object> = inputs.iter()
+                .map(|input| decode_file(input, &instruction_set))
+                .collect();
+
+            match link(&objects, &instruction_set) {
+                Ok(object) => write_file(&output, &encode(&object, version, &instruction_set)),
+                Err(err) => {
+                    // errors refer to objects by position, so name the file instead
+                    eprintln!("link failed: {}", err);
+                    for (index, input) in inputs.iter().enumerate() {
+                        eprintln!("  object {} is {}", index, input);
+                    }
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        "convert" => {
+            // decoding reads any version, so converting is just encoding it again
+            let (inputs, output, version) = split_output(&args[1..]);
+            let ([input], Some(output)) = (inputs.as_slice(), output) else {
+                eprintln!("{}", USAGE);
+                std::process::exit(1);
+            };
+
+            let object = decode_file(input, &instruction_set);
+            write_file(&output, &encode(&object, version, &instruction_set));
+        }
+
+        "inspect" => {
+            let inspection = Object::inspect(&read_file(&args[1]), &instruction_set);
+
+            println!("size: {} bytes", inspection.size);
+
+            if let Some(version) = inspection.version {
+                println!("version: {}", version);
+            }
+
+            if let Some(flags) = inspection.flags {
+                let mut names = Vec::new();
+                if flags & FLAG_NEEDS_LINKING != 0 {
+                    names.push("needs linking");
+                }
+                if flags & FLAG_CHECKSUMS != 0 {
+                    names.push("checksums");
+                }
+
+                println!("flags: {:#04x} ({})", flags, names.join(", "));
+            }
+
+            println!("sections:");
+            for section in inspection.sections.iter() {
+                let checksum = match section.checksum {
+                    Checksum::NotStored => "not stored".to_string(),
+                    Checksum::Unchecked => "not checked".to_string(),
+                    Checksum::Valid(crc) => format!("ok ({:08x})", crc),
+                    Checksum::Invalid { expected, found } => format!("MISMATCH (expected {:08x}, found {:08x})", expected, found),
+                };
+
+                println!("  {:<8} kind {:<3} offset {:<8} length {:<8} checksum {}", section_name(section.kind), section.kind, section.offset, section.length, checksum);
+            }
+
+            match inspection.error {
+                None => println!("status: ok"),
+                Some(err) => {
+                    match err.offset() {
+                        Some(offset) => println!("status: malformed at offset {}: {}", offset, err),
+                        None => println!("status: malformed: {}", err),
+                    }
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        "disasm" => {
+            let mut object = decode_file(&args[1], &instruction_set);
+
+            // an object that still needs linking can't be loaded, so list its imports and show the placeholders
+            for import in object.imports.drain(..) {
+                let references: Vec<String> = import.references.iter().map(|reference| reference.to_string()).collect();
+                println!("; import {} at {}", import.name, references.join(", "));
+            }
+
+            for export in object.exports.iter() {
+                println!("; export {} at {}", export.name, export.address);
+            }
+
+            if let Err(err) = vm.load_object(object) {
+                eprintln!("couldn't load {}: {}", args[1], err);
+                std::process::exit(1);
+            }
+            print!("{}", vm.dump());
+        }
+
+        "profile" => {
+            let mut bytes = read_file(&args[1]);
+            let json = args[2..].iter().any(|arg| arg == "--json");
+
+            load(&mut vm, &args[1], &mut bytes);
+            vm.enable_profiling();
+            let result = drive(&mut vm);
+
+            let profile = vm.take_profile().unwrap();
+
+            if json {
+                println!("{}", profile.to_json());
+            } else {
+                print!("{}", profile);
             }
 
-            vm.load_bytecode(bytes);
+            exit_on_error(&vm, result);
+        }
+
+        "trace" => {
+            let mut bytes = read_file(&args[1]);
+            let options = &args[2..];
+
+            let mut json = false;
+            let mut filter = TraceFilter::default();
+            let mut from = 0;
+            let mut to = usize::MAX;
+
+            let mut i = 0;
+            while i < options.len() {
+                match options[i].as_str() {
+                    "--json" => json = true,
+                    "--from" => {
+                        i += 1;
+                        from = parse_address(options.get(i));
+                        filter.addresses = Some(from..to);
+                    }
+                    "--to" => {
+                        i += 1;
+                        // inclusive, so the range ends one after
+                        to = parse_address(options.get(i)).saturating_add(1);
+                        filter.addresses = Some(from..to);
+                    }
+                    "--opcode" => {
+                        i += 1;
+                        let names = options.get(i).map(|names| names.as_str()).unwrap_or("");
+                        let mut opcodes = Vec::new();
+
+                        for name in names.split(',') {
+                            match instruction_set.iter().find(|instruction| instruction.name.eq_ignore_ascii_case(name)) {
+                                Some(instruction) => opcodes.push(instruction.opcode),
+                                None => {
+                                    eprintln!("unknown opcode {}", name);
+                                    std::process::exit(1);
+                                }
+                            }
+                        }
+
+                        filter.opcodes = Some(opcodes);
+                    }
+                    option => {
+                        eprintln!("invalid option {}", option);
+                        std::process::exit(1);
+                    }
+                }
+                i += 1;
+            }
+
+            // the trace goes to stderr so it doesn't get mixed up with the program's output
+            let tracer: Box<dyn Tracer> = if json {
+                Box::new(FilteredTracer::new(JsonTracer::new(io::stderr()), filter))
+            } else {
+                Box::new(FilteredTracer::new(TextTracer::new(io::stderr()), filter))
+            };
+
+            load(&mut vm, &args[1], &mut bytes);
+            vm.set_tracer(Some(tracer));
+            let result = drive(&mut vm);
+            exit_on_error(&vm, result);
+        }
+
+        #[cfg(feature = "sync")]
+        "actors" => {
+            // every file gets its own vm on its own thread, a file given more than once is only decoded once
+            let mut runtime = ActorRuntime::new();
+            let mut programs: HashMap<&str, Program> = HashMap::new();
+
+            for path in &args[1..] {
+                let program = programs.entry(path).or_insert_with(|| match Program::decode(&read_file(path), &instruction_set) {
+                    Ok(program) => program,
+                    Err(err) => {
+                        eprintln!("couldn't load {}: {}", path, err);
+                        std::process::exit(1);
+                    }
+                });
+
+                let mut vm = VM::with_program(instruction_set.clone(), program.clone(), None);
+                vm.set_overflow_mode(overflow_mode);
+                runtime.spawn(vm);
+            }
+
+            let mut failed = false;
+            for (path, state) in args[1..].iter().zip(runtime.run()) {
+                if let RunState::Error(err) = state {
+                    eprintln!("error in {}: {}", path, err);
+                    failed = true;
+                }
+            }
 
-            vm.run();
+            if failed {
+                std::process::exit(1);
+            }
         }
+
         _ => {
             eprintln!("invalid argument");
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    }
+}
+
+// every instruction the cli knows about
+fn instruction_set() -> Vec<Instruction> {
+    #[cfg(feature = "sync")]
+    return [DEFAULT_INSTRUCTION_SET, MATH_INSTRUCTION_SET, ACTOR_INSTRUCTION_SET].concat();
+
+    #[cfg(not(feature = "sync"))]
+    return [DEFAULT_INSTRUCTION_SET, MATH_INSTRUCTION_SET].concat();
+}
+
+// pulls "-o <file>" and "--bytecode-version <version>" out of the arguments, giving back the rest, the file and the version
+fn split_output(args: &[String]) -> (Vec<String>, Option<String>, Option<u8>) {
+    let mut rest = Vec::new();
+    let mut output = None;
+    let mut version = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            output = args.next().cloned();
+        } else if arg == "--bytecode-version" {
+            match args.next().map(|version| version.parse::<u8>()) {
+                Some(Ok(v)) if (BYTECODE_VERSION_1..=LATEST_BYTECODE_VERSION).contains(&v) => version = Some(v),
+                _ => {
+                    eprintln!("expected a bytecode version from {} to {}", BYTECODE_VERSION_1, LATEST_BYTECODE_VERSION);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+
+    (rest, output, version)
+}
+
+// pulls out --overflow <mode> wherever it is, it applies to every vm we make
+fn split_overflow_mode(args: Vec<String>) -> (Vec<String>, OverflowMode) {
+    let mut rest = Vec::new();
+    let mut mode = OverflowMode::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg != "--overflow" {
+            rest.push(arg);
+            continue;
+        }
+
+        match args.next().as_deref().and_then(OverflowMode::from_name) {
+            Some(m) => mode = m,
+            None => {
+                eprintln!("expected an overflow mode, wrapping, checked, saturating or promote");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    (rest, mode)
+}
+
+// encodes as the given version, or the latest one, bailing out if it can't be
+fn encode(object: &Object, version: Option<u8>, instruction_set: &[Instruction]) -> Vec<u8> {
+    let encoded = match version {
+        Some(version) => object.encode_version(version, instruction_set),
+        None => object.encode(instruction_set),
+    };
+
+    match encoded {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("couldn't encode: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn write_file(path: &str, bytes: &[u8]) {
+    if let Err(err) = fs::write(path, bytes) {
+        eprintln!("couldn't write {}: {}", path, err);
+        std::process::exit(1);
+    }
+}
+
+// loads bytecode into the vm, bailing out if it's malformed
+fn load(vm: &mut VM, name: &str, bytes: &mut [u8]) {
+    if let Err(err) = vm.load_bytecode(bytes) {
+        eprintln!("couldn't load {}: {}", name, err);
+        std::process::exit(1);
+    }
+}
+
+// reads and decodes a bytecode or object file, bailing out if it's malformed
+fn decode_file(path: &str, instruction_set: &[Instruction]) -> Object {
+    match Object::decode(&read_file(path), instruction_set) {
+        Ok(object) => object,
+        Err(err) => {
+            eprintln!("couldn't load {}: {}", path, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+// reads the entirety of a file, bailing out if we can't
+fn read_file(path: &str) -> Vec<u8> {
+    match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("couldn't read {}: {}", path, err);
+            std::process::exit(1);
         }
     }
+}
 
+// runs the vm to completion, reading stdin whenever it needs input
+fn drive(vm: &mut VM) -> Result<(), VMError> {
+    let mut state = vm.run();
+
+    loop {
+        state = match state {
+            RunState::Halted => return Ok(()),
+            RunState::Error(err) => return Err(err),
+            RunState::Yielded(value) => {
+                // nobody's on the other end, so just show it and carry on
+                println!("{:?}", value);
+                vm.resume(None)
+            }
+            RunState::NeedsInput => vm.resume(Some(read_input())),
+            RunState::BudgetExhausted => vm.resume(None),
+        };
+    }
+}
+
+// reads a line from stdin, as a number if it looks like one otherwise as a string
+fn read_input() -> Value {
+    let mut line = String::new();
+
+    match io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => Value::Nil,
+        Ok(_) => {
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            match line.trim().parse::<f64>() {
+                Ok(n) => Value::Number(n),
+                Err(_) => Value::Str(intern_string(line.to_string())),
+            }
+        }
+    }
+}
+
+// reports an error that went uncaught by the program, along with where it came from
+fn exit_on_error(vm: &VM, result: Result<(), VMError>) {
+    if let Err(err) = result {
+        eprintln!("error at {}: {}", vm.describe_address(vm.last_address()), err);
+        std::process::exit(1);
+    }
+}
+
+// parses the address given after an option, bailing out if there isn't one
+fn parse_address(arg: Option<&String>) -> usize {
+    match arg.map(|arg| arg.parse::<usize>()) {
+        Some(Ok(address)) => address,
+        _ => {
+            eprintln!("expected an address");
+            std::process::exit(1);
+        }
+    }
 }